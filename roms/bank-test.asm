@@ -0,0 +1,37 @@
+; ROM bank test
+; Banks 2 and 3 each have a routine at $4000. Switching the bank at $FD20
+; changes which one a JSR $4000 ends up in.
+
+main:
+    LDA #$02
+    STA $FD20        ; Bank 2 into the window
+    JSR $4000
+    STA $F0
+    DBG $F0          ; 2
+
+    LDA #$03
+    STA $FD20        ; Bank 3 into the window
+    JSR $4000
+    STA $F1
+    DBG $F1          ; 3
+
+    LDA $FD20
+    STA $F2
+    DBG $F2          ; 3 (the current bank)
+
+    LDA #$09
+    STA $FD20        ; There is no bank 9, so bank 3 stays mapped
+    LDA $FD20
+    STA $F3
+    DBG $F3          ; 3
+    HLT
+
+.bank 2
+bank2:
+    LDA #$02
+    RTS
+
+.bank 3
+bank3:
+    LDA #$03
+    RTS
@@ -0,0 +1,31 @@
+; Decimal mode test
+; SED makes ADC/SBC work on packed BCD (two decimal digits per byte), so scores
+; can be added up and drawn digit by digit without any conversion tables.
+
+main:
+    SED              ; Decimal mode on
+    CLC
+    LDA #$58
+    ADC #$46         ; 58 + 46 = 104 -> A = $04 and carry set
+    STA $F0
+    DBG $F0          ; 4
+
+    LDA #$00
+    ADC #$00         ; Pick up the carry into the hundreds byte -> $01
+    STA $F1
+    DBG $F1          ; 1
+
+    SEC              ; No borrow
+    LDA #$10
+    SBC #$01         ; 10 - 01 = 09, carry stays set
+    STA $F2
+    DBG $F2          ; 9
+
+    SEC
+    LDA #$00
+    SBC #$01         ; 00 - 01 = 99 with a borrow, carry cleared
+    STA $F3
+    DBG $F3          ; 153 ($99)
+
+    CLD              ; Back to binary
+    HLT
@@ -0,0 +1,63 @@
+; Block move and fill test
+; FIL clears the screen and MOV copies rows around without a loop of STA
+; instructions. Both read their source, destination and length from a 6 byte
+; parameter block in zero page, here at $E0.
+
+main:
+    ; Fill the whole display ($F000-$FBFF) with colour 4 (blue)
+    LDA #$00
+    STA $E2
+    LDA #$F0
+    STA $E3          ; Destination $F000
+    LDA #$00
+    STA $E4
+    LDA #$0C
+    STA $E5          ; Length $0C00
+    LDA #$04
+    FIL $E0
+
+    ; Draw a red pixel at the start of the first row
+    LDA #$01
+    STA $F000
+
+    ; Copy the first 256 bytes one row down. The blocks don't overlap
+    LDA #$00
+    STA $E0
+    LDA #$F0
+    STA $E1          ; Source $F000
+    LDA #$00
+    STA $E2
+    LDA #$F1
+    STA $E3          ; Destination $F100
+    LDA #$00
+    STA $E4
+    LDA #$01
+    STA $E5          ; Length $0100
+    MOV $E0
+
+    LDA $F100
+    STA $F0
+    DBG $F0          ; 1
+    LDA $FBFF
+    STA $F1
+    DBG $F1          ; 4
+
+    ; Shift the first row right by one pixel. Source and destination overlap,
+    ; so the red pixel must end up at $F001 and not be smeared along the row
+    LDA #$01
+    STA $E2          ; Destination $F001
+    LDA #$F0
+    STA $E3
+    LDA #$FF
+    STA $E4
+    LDA #$00
+    STA $E5          ; Length $00FF
+    MOV $E0
+
+    LDA $F001
+    STA $F2
+    DBG $F2          ; 1
+    LDA $F002
+    STA $F3
+    DBG $F3          ; 4
+    HLT
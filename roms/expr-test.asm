@@ -0,0 +1,73 @@
+; Constants and expressions test
+; Names for addresses and sizes instead of magic numbers, and operands worked out
+; by the assembler.
+
+SCREEN = $F000              ; Display buffer
+ROW_BYTES = 32
+ROW .equ 2
+.equ RESULT, $F0            ; Zero page scratch
+LAST = table_end - 1        ; Constants may use labels defined further down
+
+main:
+    LDX #1
+    LDA #$5A
+    STA SCREEN+ROW_BYTES*ROW,X  ; $F041
+    LDA SCREEN + $41
+    STA RESULT
+    DBG RESULT               ; 90
+
+    LDA #(3 + 4) * 2 - 1     ; Parentheses and precedence
+    STA RESULT+1
+    DBG RESULT+1             ; 13
+
+    LDA #%1100 | 1 << 4 ^ $03 & $FF
+    STA RESULT+2
+    DBG RESULT+2             ; 31
+
+    LDA #<table              ; Low and high bytes of an address
+    STA RESULT+3
+    LDA #>table
+    STA RESULT+4
+    LDY #2
+    LDA (RESULT+3),Y         ; table + 2
+    STA RESULT+5
+    DBG RESULT+5             ; 30
+
+    LDA LAST                 ; Last byte of the table
+    STA RESULT+6
+    DBG RESULT+6             ; 40
+
+    LDA #table_end - table   ; Table length
+    STA RESULT+7
+    DBG RESULT+7             ; 4
+
+    JMP * + 4                ; `*` is the address of this JMP, skip it and the HLT
+    HLT
+    LDA #'A'
+    STA RESULT+8
+    DBG RESULT+8             ; 65
+
+    LDA #';'                 ; Quoted ; , and spaces aren't comments or separators
+    STA RESULT+9
+    DBG RESULT+9             ; 59
+    LDX #1
+    LDA punctuation,X
+    STA RESULT+10
+    DBG RESULT+10            ; 32
+    LDA punctuation+2
+    STA RESULT+11
+    DBG RESULT+11            ; 34
+
+    LDA #-1                  ; Negative bytes are two's complement
+    CLC
+    ADC #3
+    STA RESULT+12
+    DBG RESULT+12            ; 2
+    HLT
+
+punctuation:
+    .byte ',', ' ', '"', ";" ; 44, 32, 34, 59
+
+table:
+    .byte 10, 10 * 2, ROW_BYTES - 2, 40
+table_end:
@@ -0,0 +1,21 @@
+; Include test
+; Pulls in macros and constants from another file and a table from a binary
+; file. Paths are relative to the file doing the including, so run this with
+; --asm roms/include-test.asm from anywhere.
+
+.include "include/show.asm"
+
+main:
+    LDX #0
+    LDA table,X
+    show RESULT          ; 5
+    LDX #3
+    LDA table,X
+    show RESULT+1        ; 40
+    LDA #table_end - table
+    show RESULT+2        ; 4
+    HLT
+
+table:
+.incbin "include/table.bin" ; 5, 10, 20, 40
+table_end:
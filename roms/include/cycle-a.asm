@@ -0,0 +1,2 @@
+; Include cycle test fixture, included by cycle-b.asm in turn
+.include "cycle-b.asm"
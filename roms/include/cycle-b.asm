@@ -0,0 +1,3 @@
+; Include cycle test fixture
+    NOP
+.include "cycle-a.asm"
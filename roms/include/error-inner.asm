@@ -0,0 +1,3 @@
+; Include chain test fixture, included by error-middle.asm
+    NOP
+    LDA #
@@ -0,0 +1,2 @@
+; Include chain test fixture, included by error-outer.asm
+.include "error-inner.asm"
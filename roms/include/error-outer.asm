@@ -0,0 +1,3 @@
+; Include chain test fixture, the error is two files down in error-inner.asm
+    NOP
+.include "error-middle.asm"
@@ -0,0 +1,2 @@
+; Include test fixture for a file that does not exist
+.include "no-such-file.asm"
@@ -0,0 +1,9 @@
+; Shared helpers for include-test.asm
+
+RESULT = $F0
+
+; Store A at a zero page address and print it
+.macro show addr
+    STA addr
+    DBG addr
+.endm
@@ -0,0 +1,37 @@
+; Local and anonymous label test
+; Every subroutine gets its own .loop, and short hops use + and - instead of
+; names at all.
+
+main:
+    LDX #3
+.loop:                   ; main.loop
+    DEX
+    BNE .loop
+    JSR count
+
+    LDY #2
+-
+    DEY
+    BNE -                ; Back to the nearest -
+    LDA #0
+    BEQ +                ; Forward to the nearest +
+    HLT
++
+    LDA #1
+    BNE ++               ; Forward to the second + from here
++
+    HLT
++
+    STA $F0
+    DBG $F0              ; 1
+    LDA #<count.loop     ; Local labels can be reached from outside by their full name
+    STA $F1
+    DBG $F1              ; 36
+    HLT
+
+count:
+    LDX #2
+.loop:                   ; count.loop, separate from main.loop
+    DEX
+    BNE .loop
+    RTS
@@ -0,0 +1,49 @@
+; Macro test
+; Macros stand in for sequences that would otherwise be copied around, like
+; storing a value and printing it or counting down a delay loop.
+
+RESULT = $F0
+
+; Store A at a zero page address and print it
+.macro show addr
+    STA addr
+    DBG addr
+.endm
+
+; Load a value, add to it and show the sum
+.macro add_show first, second, addr
+    LDA #first
+    CLC
+    ADC #second
+    show addr            ; Macros can call other macros
+.endm
+
+; Load A from any address, including indexed ones
+.macro load addr
+    LDA addr
+.endm
+
+; Count X down from a value. The loop label is new in every expansion.
+.macro delay count
+    LDX #count
+wait:
+    DEX
+    BNE wait
+.endm
+
+main:
+    LDA #42
+    show RESULT          ; 42
+    add_show 10, 5, RESULT+1   ; 15
+    add_show $20, 2*3, RESULT+2 ; 38
+
+    delay 3
+    TXA
+    show RESULT+3        ; 0
+    delay 5              ; A second copy, with its own loop label
+    LDA #7
+    show RESULT+4        ; 7
+    LDX #1
+    load RESULT,X        ; One argument, RESULT+1
+    show RESULT+5        ; 15
+    HLT
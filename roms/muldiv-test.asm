@@ -0,0 +1,38 @@
+; Multiply and divide test
+; MUL and DIV replace the usual shift-and-add loops for things like turning a
+; tile row and column into an offset into the display buffer.
+
+main:
+    LDA #200
+    LDY #3
+    MUL              ; 200 * 3 = 600 = $0258 -> A = $58, X = $02, carry set
+    STA $F0
+    STX $F1
+    DBG $F0          ; 88
+    DBG $F1          ; 2
+
+    DIV              ; 600 / 3 = 200 remainder 0 -> A = 200, X = 0
+    STA $F2
+    DBG $F2          ; 200
+
+    LDX #$00
+    LDA #100
+    LDY #7
+    DIV              ; 100 / 7 = 14 remainder 2, carry set
+    STA $F3
+    STX $F4
+    DBG $F3          ; 14
+    DBG $F4          ; 2
+
+    LDY #0
+    DIV              ; Divide by zero: V is set and A stays 14
+    BVC fail
+    STA $F5
+    DBG $F5          ; 14
+    HLT
+
+fail:
+    LDA #$FF
+    STA $F5
+    DBG $F5          ; 255 (should not happen)
+    HLT
@@ -0,0 +1,37 @@
+; Random number test
+; Every read of $FD00 gives a new random byte. Writing a seed to $FD01/$FD02
+; restarts the sequence, so the same seed always gives the same numbers.
+
+main:
+    ; Seed with $1234 and take two numbers
+    LDA #$34
+    STA $FD01
+    LDA #$12
+    STA $FD02
+    LDA $FD00
+    STA $F0
+    LDA $FD00
+    STA $F1
+
+    ; Reseed with the same value, the numbers must repeat
+    LDA #$12
+    STA $FD02
+    LDA $FD00
+    CMP $F0
+    BNE fail
+    LDA $FD00
+    CMP $F1
+    BNE fail
+
+    DBG $F0          ; Same two numbers on every run
+    DBG $F1
+    LDA #$01
+    STA $F2
+    DBG $F2          ; 1
+    HLT
+
+fail:
+    LDA #$00
+    STA $F2
+    DBG $F2          ; 0 (should not happen)
+    HLT
@@ -101,12 +101,12 @@ play_sound:
     
     ; Combine channel and note
     LDA $86          ; Channel (0-3)
-    ;ASL A            ; Shift left 6 bits
-    ;ASL A
-    ;ASL A
-    ;ASL A
-    ;ASL A
-    ;ASL A
+    ASL A            ; Shift left 6 bits
+    ASL A
+    ASL A
+    ASL A
+    ASL A
+    ASL A
     ORA $87          ; OR with note value
     
     ; Play the sound
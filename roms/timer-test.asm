@@ -0,0 +1,40 @@
+; Timer test
+; Timer 0 interrupts every 1000 cycles and the handler counts the ticks in $F0.
+; The main loop just waits for 5 ticks, however long each pass of it takes.
+
+    JMP main
+
+; IRQ handler at $0003 (the IRQ vector at $FFFE is pointed here below)
+irq:
+    INC $F0
+    LDA #$83         ; Acknowledge the timer and keep it running with its IRQ on
+    STA $FD13
+    RTI
+
+main:
+    LDA #$03
+    STA $FFFE
+    LDA #$00
+    STA $FFFF        ; IRQ vector -> $0003
+    STA $F0
+
+    LDA #$E8
+    STA $FD10
+    LDA #$03
+    STA $FD11        ; Reload value 1000
+    LDA #$00
+    STA $FD12        ; Count every cycle
+    LDA #$03
+    STA $FD13        ; Enable the timer and its IRQ
+    CLI
+
+wait:
+    LDA $F0
+    CMP #$05
+    BNE wait
+
+    SEI
+    LDA #$00
+    STA $FD13        ; Stop the timer
+    DBG $F0          ; 5
+    HLT
@@ -1,9 +1,8 @@
-use std::sync::{Arc, Mutex};
 use rodio::{OutputStream, Source, Sink};
 use std::time::Duration;
 use rand::prelude::*;
 
-use crate::memory::{Memory, AUDIO_START, AUDIO_SIZE};
+use helios::memory::{Memory, AUDIO_SIZE};
 
 // Constants
 const SAMPLE_RATE: u32 = 44100;
@@ -134,7 +133,6 @@ impl Source for MixedChannelSource {
 }
 
 pub struct Audio {
-    memory: Arc<Mutex<Memory>>,
     _stream: OutputStream,
     sink: Sink,
     channels: Vec<Channel>,
@@ -142,7 +140,7 @@ pub struct Audio {
 }
 
 impl Audio {
-    pub fn new(memory: Arc<Mutex<Memory>>) -> Self {
+    pub fn new() -> Self {
         // Create output stream
         let (_stream, stream_handle) = OutputStream::try_default().expect("Failed to create audio output stream");
 
@@ -163,7 +161,6 @@ impl Audio {
         sink.set_volume(0.5);
 
         Self {
-            memory,
             _stream,
             sink,
             channels,
@@ -171,8 +168,8 @@ impl Audio {
         }
     }
     
-    pub fn update(&mut self) {
-        let memory = self.memory.lock().unwrap();
+    // Pick up notes written to the audio buffer since the last update
+    pub fn update(&mut self, memory: &Memory) {
         let audio_buffer = memory.get_audio_buffer();
         
         let mut channels_updated = false;
@@ -0,0 +1,56 @@
+// src/bus.rs
+use std::sync::{Arc, Mutex};
+
+// A write the bus refused, reported to the CPU after the instruction that made it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WriteFault {
+    pub address: u16,
+    pub value: u8,
+    pub halt: bool, // Stop the CPU so the host can inspect it
+}
+
+// Everything the CPU reads or writes goes through a Bus. Plain memory is one
+// implementation; test harnesses and memory-mapped devices can provide others.
+pub trait Bus {
+    // Reads take &mut self so memory-mapped devices can react to being read
+    fn read(&mut self, address: u16) -> u8;
+    
+    fn write(&mut self, address: u16, value: u8);
+    
+    // Called after every instruction with the cycles it took so devices can keep time
+    fn tick(&mut self, _cycles: u32) {}
+    
+    // Whether a device on the bus is holding the IRQ line
+    fn irq(&self) -> bool {
+        false
+    }
+    
+    // The first write refused since the last call, if any
+    fn take_write_fault(&mut self) -> Option<WriteFault> {
+        None
+    }
+}
+
+// Lets the CPU run against a bus that other threads (display, audio) also hold.
+// Every access takes the lock, so prefer owning the bus when nothing else needs it.
+impl<B: Bus> Bus for Arc<Mutex<B>> {
+    fn read(&mut self, address: u16) -> u8 {
+        self.lock().unwrap().read(address)
+    }
+    
+    fn write(&mut self, address: u16, value: u8) {
+        self.lock().unwrap().write(address, value);
+    }
+    
+    fn tick(&mut self, cycles: u32) {
+        self.lock().unwrap().tick(cycles);
+    }
+    
+    fn irq(&self) -> bool {
+        self.lock().unwrap().irq()
+    }
+    
+    fn take_write_fault(&mut self) -> Option<WriteFault> {
+        self.lock().unwrap().take_write_fault()
+    }
+}
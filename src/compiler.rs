@@ -1,27 +1,126 @@
 // src/compiler.rs
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
 
+use crate::expression::{evaluate, is_symbol_name, Evaluation, Value};
+use crate::memory::{BANK_SIZE, BANK_WINDOW_START, EXTRA_BANKS_START};
+
+// Deepest chain of macros calling macros, so a macro that calls itself is an error instead of a hang
+const MAX_MACRO_DEPTH: usize = 32;
+
+// Anonymous labels are renamed to this followed by their number. Source labels
+// can't contain dots and macro labels get a single one, so these names are free.
+const ANONYMOUS_PREFIX: &str = "anonymous..";
+
+// A line of source with comments stripped and includes spliced in
+#[derive(Clone)]
+struct SourceLine {
+    text: String,
+    line_num: usize,     // Line errors are reported against; the outermost call for macro lines
+    origin: String,      // Which included file the line is in, empty for the main file
+    macros: Vec<String>, // Macros the line was expanded from, outermost first
+}
+
+// A `.macro name param, ... .endm` definition
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+// Assembles source that isn't from a file. Includes are relative to the working directory.
 pub fn compile(source: &str) -> Result<Vec<u8>, String> {
+    compile_source(source, None)
+}
+
+// Assembles a file. Includes are relative to the file that includes them.
+pub fn compile_file(path: &Path) -> Result<Vec<u8>, String> {
+    let source = fs::read_to_string(path)
+        .map_err(|err| format!("Can't read {}: {}", path.display(), err))?;
+    compile_source(&source, Some(path))
+}
+
+fn compile_source(source: &str, path: Option<&Path>) -> Result<Vec<u8>, String> {
+    let mut open_files = Vec::new();
+    if let Some(path) = path {
+        open_files.extend(path.canonicalize().ok());
+    }
+    let mut lines = Vec::new();
+    read_lines(source, path, &mut Vec::new(), &mut open_files, &mut lines)?;
+    let mut lines = expand_macros(lines)?;
+    resolve_local_labels(&mut lines)?;
+
+    let mut current_line = 0;
+    assemble(&lines, &mut current_line).map_err(|error| match lines.get(current_line) {
+        Some(line) => locate_error(error, line),
+        None => error,
+    })
+}
+
+// Errors say "Line n: ...", which isn't enough for lines from an included file
+// or a macro, so add where the line came from
+fn locate_error(error: String, line: &SourceLine) -> String {
+    let mut context = Vec::new();
+    if !line.macros.is_empty() {
+        context.push(format!("in macro {}", line.macros.join(" -> ")));
+    }
+    if !line.origin.is_empty() {
+        context.push(line.origin.clone());
+    }
+    if context.is_empty() {
+        error
+    } else {
+        format!("{} ({})", error, context.join(", "))
+    }
+}
+
+// Assembles macro-expanded source, keeping `current_line` at the index of the
+// line being worked on so errors can be traced back to it
+fn assemble(lines: &[SourceLine], current_line: &mut usize) -> Result<Vec<u8>, String> {
     let mut binary = Vec::new();
     let mut labels = HashMap::new();
-    let mut unresolved_jumps = Vec::new();
+    let mut constants = HashMap::new();
+    let mut symbols = HashSet::new(); // Every label and constant name, to catch duplicates
+    let mut pending_constants = Vec::new(); // Constants that use symbols defined further down
+    let mut zero_page_lines = HashSet::new(); // Indices of lines the first pass sized with a zero page operand
 
-    // First pass: Collect all labels
+    // First pass: Collect all labels and constants
     let mut current_address = 0;
-    for (line_num, line) in source.lines().enumerate() {
-        let line_num = line_num + 1; // 1-based line numbering
-        let line = line.trim();
+    for (index, source_line) in lines.iter().enumerate() {
+        *current_line = index;
+        let line_num = source_line.line_num;
+        let line = source_line.text.as_str();
 
         // Skip empty lines and comments
-        if line.is_empty() || line.starts_with(';') {
+        if line.is_empty() {
             continue;
         }
 
-        // Check if line has a label
-        if line.ends_with(':') {
+        if let Some((name, value)) = parse_constant(line) {
+            if !symbols.insert(name.to_string()) {
+                return Err(format!("Line {}: Symbol {} is already defined", line_num, name));
+            }
+            match evaluate(value, line_num, current_address, &|name| lookup_symbol(name, &labels, &constants))? {
+                Evaluation::Known(value) => {
+                    constants.insert(name.to_string(), value);
+                },
+                Evaluation::Undefined(_) => pending_constants.push((index, name, value, current_address)),
+            }
+        } else if line.ends_with(':') {
+            // Check if line has a label
             let label = line[..line.len() - 1].trim();
+            if !symbols.insert(label.to_string()) {
+                return Err(format!("Line {}: Symbol {} is already defined", line_num, label));
+            }
             labels.insert(label.to_string(), current_address);
-        } else if !line.starts_with('.') { // Not a directive
+        } else if let Some(directive) = parse_directive(line, line_num, current_address, &|name| lookup_symbol(name, &labels, &constants), false)? {
+            // Directives move the address or take up space, labels after them have to agree
+            current_address = match directive {
+                Directive::Bank(bank) => bank_location(bank).1,
+                Directive::Org(address) => address,
+                Directive::Data(bytes) => current_address.wrapping_add(bytes.len() as u16),
+            };
+        } else {
             // Count the bytes for the instruction
             let tokens: Vec<&str> = line.split_whitespace().collect();
             if tokens.is_empty() {
@@ -30,19 +129,43 @@ pub fn compile(source: &str) -> Result<Vec<u8>, String> {
 
             match tokens[0].to_uppercase().as_str() {
                 // Single byte instructions
-                "NOP" | "TAX" | "TAY" | "TXA" | "TYA" | "INX" | "INY" | "DEX" | "DEY" | "RTS" | "BRK" | "HLT" => {
+                "NOP" | "TAX" | "TAY" | "TXA" | "TYA" | "INX" | "INY" | "DEX" | "DEY" | "RTS" | "BRK" | "HLT" |
+                "TSX" | "TXS" | "PHA" | "PLA" | "PHP" | "PLP" | "CLC" | "SEC" | "CLV" |
+                "SEI" | "CLI" | "RTI" | "SED" | "CLD" | "MUL" | "DIV" => {
                     current_address += 1;
                 },
 
                 // Two or three byte instructions (opcode + operand)
                 "LDA" | "LDX" | "LDY" | "STA" | "STX" | "STY" | "ADC" | "SBC" | "AND" | "ORA" | "EOR" |
-                "INC" | "DEC" | "CMP" | "CPX" | "CPY" | "BEQ" | "BNE" | "BCS" | "BCC" | "BMI" | "BPL" | "DBG" | "SND" => {
+                "INC" | "DEC" | "CMP" | "CPX" | "CPY" | "BEQ" | "BNE" | "BCS" | "BCC" | "BMI" | "BPL" | "BVS" | "BVC" |
+                "BIT" | "DBG" | "SND" | "MOV" | "FIL" => {
                     if tokens.len() < 2 {
                         return Err(format!("Line {}: Missing operand for instruction: {}", line_num, line));
                     }
 
-                    let operand = tokens[1];
-                    current_address += get_instruction_size(tokens[0], operand)?;
+                    // Operands may be written with spaces, e.g. "$F000, Y"
+                    let operand = compact_operand(split_first_word(line).1);
+                    let lookup = |name: &str| lookup_symbol(name, &labels, &constants);
+                    let (operand, zero_page) = resolve_operand(tokens[0], &operand, line_num, current_address, &lookup, None)?;
+                    if zero_page {
+                        zero_page_lines.insert(index);
+                    }
+                    current_address += get_instruction_size(tokens[0], &operand)?;
+                },
+
+                // Shifts and rotates: one byte on the accumulator, otherwise sized by operand
+                "ASL" | "LSR" | "ROL" | "ROR" => {
+                    let operand = compact_operand(split_first_word(line).1);
+                    if operand.is_empty() || operand.eq_ignore_ascii_case("A") {
+                        current_address += 1;
+                    } else {
+                        let lookup = |name: &str| lookup_symbol(name, &labels, &constants);
+                        let (operand, zero_page) = resolve_operand(tokens[0], &operand, line_num, current_address, &lookup, None)?;
+                        if zero_page {
+                            zero_page_lines.insert(index);
+                        }
+                        current_address += get_instruction_size(tokens[0], &operand)?;
+                    }
                 },
 
                 // Three byte instructions (opcode + 2 byte operand)
@@ -60,14 +183,74 @@ pub fn compile(source: &str) -> Result<Vec<u8>, String> {
         }
     }
 
+    // Constants can refer to symbols defined after them, which are all known now.
+    // Keep going while that resolves more of them; whatever is left is undefined or circular.
+    while !pending_constants.is_empty() {
+        let mut still_pending = Vec::new();
+        for (index, name, value, address) in pending_constants.iter().copied() {
+            *current_line = index;
+            match evaluate(value, lines[index].line_num, address, &|name| lookup_symbol(name, &labels, &constants))? {
+                Evaluation::Known(value) => {
+                    constants.insert(name.to_string(), value);
+                },
+                Evaluation::Undefined(symbol) => still_pending.push((index, name, value, address, symbol)),
+            }
+        }
+        if still_pending.len() == pending_constants.len() {
+            let (index, name, _, _, symbol) = &still_pending[0];
+            *current_line = *index;
+            return Err(format!("Line {}: Can't work out {}: {} is undefined or circular", lines[*index].line_num, name, symbol));
+        }
+        pending_constants = still_pending.into_iter().map(|(index, name, value, address, _)| (index, name, value, address)).collect();
+    }
+
     // Second pass: Generate binary code
     current_address = 0;
-    for (line_num, line) in source.lines().enumerate() {
-        let line_num = line_num + 1; // 1-based line numbering
-        let line = line.trim();
+    let mut bank_offset = 0; // Image offset of address 0 in the current bank
+    let mut bank = None; // Set by .bank 1 and up, whose code has to stay inside the window
+    for (index, source_line) in lines.iter().enumerate() {
+        *current_line = index;
+        let line_num = source_line.line_num;
+        let line = source_line.text.as_str();
 
-        // Skip empty lines, comments, and labels
-        if line.is_empty() || line.starts_with(';') || line.ends_with(':') || line.starts_with('.') {
+        // Skip empty lines, comments, labels and constants
+        if line.is_empty() || line.ends_with(':') || parse_constant(line).is_some() {
+            continue;
+        }
+        
+        if let Some(directive) = parse_directive(line, line_num, current_address, &|name| lookup_symbol(name, &labels, &constants), true)? {
+            match directive {
+                Directive::Bank(number) => {
+                    // Move output to where bank n lives in the ROM image
+                    let (offset, address) = bank_location(number);
+                    if binary.len() > offset {
+                        return Err(format!("Line {}: Bank {} starts at image offset ${:X}, but earlier code already runs past it", line_num, number, offset));
+                    }
+                    binary.resize(offset, 0);
+                    bank_offset = offset - address as usize;
+                    bank = if number > 0 { Some(number) } else { None };
+                    current_address = address;
+                },
+                Directive::Org(address) => {
+                    if let Some(bank) = bank {
+                        if !bank_window().contains(&(address as usize)) {
+                            return Err(format!("Line {}: .org ${:04X} is outside bank {}, which runs at $4000-$7FFF", line_num, address, bank));
+                        }
+                    }
+                    // Pad up to the new address. Going backwards would overwrite earlier output.
+                    let offset = bank_offset + address as usize;
+                    if binary.len() > offset {
+                        return Err(format!("Line {}: .org ${:04X} is behind code that was already placed (up to ${:04X})", line_num, address, current_address));
+                    }
+                    binary.resize(offset, 0);
+                    current_address = address;
+                },
+                Directive::Data(bytes) => {
+                    binary.extend_from_slice(&bytes);
+                    current_address = current_address.wrapping_add(bytes.len() as u16);
+                    check_bank_end(bank, binary.len() - bank_offset, line_num)?;
+                },
+            }
             continue;
         }
 
@@ -77,6 +260,19 @@ pub fn compile(source: &str) -> Result<Vec<u8>, String> {
         }
         
         let instruction = tokens[0].to_uppercase();
+        // Operands may be written with spaces, e.g. "$F000, Y"
+        let operand = compact_operand(split_first_word(line).1);
+        // Evaluate expressions down to plain literals, sized the way the first pass sized them
+        let operand = match instruction.as_str() {
+            _ if operand.is_empty() => operand,
+            "ASL" | "LSR" | "ROL" | "ROR" if operand.eq_ignore_ascii_case("A") => operand,
+            _ => {
+                let lookup = |name: &str| lookup_symbol(name, &labels, &constants);
+                let zero_page = zero_page_lines.contains(&index);
+                resolve_operand(&instruction, &operand, line_num, current_address, &lookup, Some(zero_page))?.0
+            },
+        };
+        let start = binary.len();
 
         match instruction.as_str() {
             "NOP" => binary.push(0xEA),
@@ -91,172 +287,175 @@ pub fn compile(source: &str) -> Result<Vec<u8>, String> {
             "DEX" => binary.push(0xCA),
             "DEY" => binary.push(0x88),
             "RTS" => binary.push(0x60),
+            "TSX" => binary.push(0xBA),
+            "TXS" => binary.push(0x9A),
+            "PHA" => binary.push(0x48),
+            "PLA" => binary.push(0x68),
+            "PHP" => binary.push(0x08),
+            "PLP" => binary.push(0x28),
+            "CLC" => binary.push(0x18),
+            "SEC" => binary.push(0x38),
+            "CLV" => binary.push(0xB8),
+            "SEI" => binary.push(0x78),
+            "CLI" => binary.push(0x58),
+            "RTI" => binary.push(0x40),
+            "SED" => binary.push(0xF8),
+            "CLD" => binary.push(0xD8),
+            "MUL" => binary.push(0x02),
+            "DIV" => binary.push(0x22),
 
             "LDA" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for LDA", line_num));
                 }
-                let operand = tokens[1];
-                compile_lda(&mut binary, &mut unresolved_jumps, operand, current_address, &labels, line_num)?;
+                let operand = operand.as_str();
+                compile_lda(&mut binary, operand, line_num)?;
             },
             "LDX" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for LDX", line_num));
                 }
-                let operand = tokens[1];
-                compile_ldx(&mut binary, &mut unresolved_jumps, operand, current_address, &labels, line_num)?;
+                let operand = operand.as_str();
+                compile_ldx(&mut binary, operand, line_num)?;
             },
             "LDY" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for LDY", line_num));
                 }
-                let operand = tokens[1];
-                compile_ldy(&mut binary, &mut unresolved_jumps, operand, current_address, &labels, line_num)?;
+                let operand = operand.as_str();
+                compile_ldy(&mut binary, operand, line_num)?;
             },
 
             "STA" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for STA", line_num));
                 }
-                let operand = tokens;
-                compile_sta(&mut binary, &mut unresolved_jumps, operand, current_address, &labels, line_num)?;
+                let operand = operand.as_str();
+                compile_sta(&mut binary, operand, line_num)?;
             },
             "STX" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for STX", line_num));
                 }
-                let operand = tokens[1];
-                compile_stx(&mut binary, &mut unresolved_jumps, operand, current_address, &labels, line_num)?;
+                let operand = operand.as_str();
+                compile_stx(&mut binary, operand, line_num)?;
             },
             "STY" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for STY", line_num));
                 }
-                let operand = tokens[1];
-                compile_sty(&mut binary, &mut unresolved_jumps, operand, current_address, &labels, line_num)?;
+                let operand = operand.as_str();
+                compile_sty(&mut binary, operand, line_num)?;
             },
 
             "ADC" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for ADC", line_num));
                 }
-                let operand = tokens[1];
-                compile_adc(&mut binary, &mut unresolved_jumps, operand, current_address, &labels, line_num)?;
+                let operand = operand.as_str();
+                compile_adc(&mut binary, operand, line_num)?;
             },
             "SBC" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for SBC", line_num));
                 }
-                let operand = tokens[1];
-                compile_sbc(&mut binary, &mut unresolved_jumps, operand, current_address, &labels, line_num)?;
+                let operand = operand.as_str();
+                compile_sbc(&mut binary, operand, line_num)?;
             },
             "AND" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for AND", line_num));
                 }
-                let operand = tokens[1];
-                compile_and(&mut binary, &mut unresolved_jumps, operand, current_address, &labels, line_num)?;
+                let operand = operand.as_str();
+                compile_and(&mut binary, operand, line_num)?;
             },
             "ORA" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for ORA", line_num));
                 }
-                let operand = tokens[1];
-                compile_ora(&mut binary, &mut unresolved_jumps, operand, current_address, &labels, line_num)?;
+                let operand = operand.as_str();
+                compile_ora(&mut binary, operand, line_num)?;
             },
             "EOR" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for EOR", line_num));
                 }
-                let operand = tokens[1];
-                compile_eor(&mut binary, &mut unresolved_jumps, operand, current_address, &labels, line_num)?;
+                let operand = operand.as_str();
+                compile_eor(&mut binary, operand, line_num)?;
             },
             "INC" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for INC", line_num));
                 }
-                let operand = tokens[1];
-                compile_inc(&mut binary, &mut unresolved_jumps, operand, current_address, &labels, line_num)?;
+                let operand = operand.as_str();
+                compile_inc(&mut binary, operand, line_num)?;
             },
             "DEC" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for DEC", line_num));
                 }
-                let operand = tokens[1];
-                compile_dec(&mut binary, &mut unresolved_jumps, operand, current_address, &labels, line_num)?;
+                let operand = operand.as_str();
+                compile_dec(&mut binary, operand, line_num)?;
             },
             "CMP" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for CMP", line_num));
                 }
-                let operand = tokens[1];
-                compile_cmp(&mut binary, &mut unresolved_jumps, operand, current_address, &labels, line_num)?;
+                let operand = operand.as_str();
+                compile_cmp(&mut binary, operand, line_num)?;
             },
             "CPX" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for CPX", line_num));
                 }
-                let operand = tokens[1];
-                compile_cpx(&mut binary, &mut unresolved_jumps, operand, current_address, &labels, line_num)?;
+                let operand = operand.as_str();
+                compile_cpx(&mut binary, operand, line_num)?;
             },
             "CPY" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for CPY", line_num));
                 }
-                let operand = tokens[1];
-                compile_cpy(&mut binary, &mut unresolved_jumps, operand, current_address, &labels, line_num)?;
+                let operand = operand.as_str();
+                compile_cpy(&mut binary, operand, line_num)?;
+            },
+            "ASL" | "LSR" | "ROL" | "ROR" => {
+                let opcodes = match instruction.as_str() {
+                    "ASL" => [0x0A, 0x06, 0x16, 0x0E, 0x1E],
+                    "LSR" => [0x4A, 0x46, 0x56, 0x4E, 0x5E],
+                    "ROL" => [0x2A, 0x26, 0x36, 0x2E, 0x3E],
+                    "ROR" => [0x6A, 0x66, 0x76, 0x6E, 0x7E],
+                    _ => unreachable!(),
+                };
+                compile_shift(&mut binary, &instruction, opcodes, &operand, line_num)?;
             },
             "JMP" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for JMP", line_num));
                 }
-                let operand = tokens[1];
-                binary.push(0x4C);
+                let operand = operand.as_str();
 
-                if operand.starts_with('$') {
-                    // Absolute address
-                    parse_and_push_value(&mut binary, operand, 2, line_num)?;
+                // JMP ($addr) jumps to the address stored at $addr
+                let (opcode, operand) = if operand.starts_with('(') && operand.ends_with(')') {
+                    (0x6C, operand[1..operand.len()-1].trim())
                 } else {
-                    // Label
-                    if let Some(&address) = labels.get(operand) {
-                        binary.push((address & 0xFF) as u8);
-                        binary.push((address >> 8) as u8);
-                    } else {
-                        // Unresolved label, add to list for second pass
-                        unresolved_jumps.push((binary.len(), operand.to_string(), 2));
-                        binary.push(0);
-                        binary.push(0);
-                    }
-                }
+                    (0x4C, operand)
+                };
+                binary.push(opcode);
+                parse_and_push_value(&mut binary, operand, 2, line_num)?;
             },
             "JSR" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for JSR", line_num));
                 }
-                let operand = tokens[1];
+                let operand = operand.as_str();
                 binary.push(0x20);
-
-                if operand.starts_with('$') {
-                    // Absolute address
-                    parse_and_push_value(&mut binary, operand, 2, line_num)?;
-                } else {
-                    // Label
-                    if let Some(&address) = labels.get(operand) {
-                        binary.push((address & 0xFF) as u8);
-                        binary.push((address >> 8) as u8);
-                    } else {
-                        // Unresolved label, add to list for second pass
-                        unresolved_jumps.push((binary.len(), operand.to_string(), 2));
-                        binary.push(0);
-                        binary.push(0);
-                    }
-                }
+                parse_and_push_value(&mut binary, operand, 2, line_num)?;
             },
-            "BEQ" | "BNE" | "BCS" | "BCC" | "BMI" | "BPL" => {
+            "BEQ" | "BNE" | "BCS" | "BCC" | "BMI" | "BPL" | "BVS" | "BVC" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for branch instruction", line_num));
                 }
-                let operand = tokens[1];
+                let operand = operand.as_str();
                 let opcode = match instruction.as_str() {
                     "BEQ" => 0xF0,
                     "BNE" => 0xD0,
@@ -264,78 +463,404 @@ pub fn compile(source: &str) -> Result<Vec<u8>, String> {
                     "BCC" => 0x90,
                     "BMI" => 0x30,
                     "BPL" => 0x10,
+                    "BVS" => 0x70,
+                    "BVC" => 0x50,
                     _ => unreachable!(),
                 };
 
                 binary.push(opcode);
 
-                if operand.starts_with('$') {
-                    // Relative address (branch target is PC + offset)
-                    let target = parse_value(operand, line_num)?;
-                    let offset = (target as i32 - (current_address + 2) as i32) as i8;
-                    binary.push(offset as u8);
-                } else {
-                    // Label
-                    if let Some(&address) = labels.get(operand) {
-                        let offset = (address as i32 - (current_address + 2) as i32) as i8;
-                        binary.push(offset as u8);
-                    } else {
-                        // Unresolved label, add to list for second pass
-                        unresolved_jumps.push((binary.len(), operand.to_string(), 1));
-                        binary.push(0);
-                    }
+                // The operand is the target address, the branch stores it relative to the next instruction
+                let target = parse_value(operand, line_num)?;
+                let offset = branch_offset(target, current_address, line_num)?;
+                binary.push(offset as u8);
+            },
+            "BIT" => {
+                if tokens.len() < 2 {
+                    return Err(format!("Line {}: Missing operand for BIT", line_num));
                 }
+                let operand = operand.as_str();
+                compile_bit(&mut binary, operand, line_num)?;
             },
             "DBG" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for DBG", line_num));
                 }
-                let operand = tokens[1];
-                compile_dbg(&mut binary, &mut unresolved_jumps, operand, current_address, &labels, line_num)?;
+                let operand = operand.as_str();
+                compile_dbg(&mut binary, operand, line_num)?;
             },
             "SND" => {
                 if tokens.len() < 2 {
                     return Err(format!("Line {}: Missing operand for SND", line_num));
                 }
-                let operand = tokens[1];
+                // The byte to play can be written as an immediate, e.g. "SND #$40"
+                let operand = operand.strip_prefix('#').unwrap_or(&operand);
                 binary.push(0x42); // Custom sound opcode
                 parse_and_push_value(&mut binary, operand, 1, line_num)?;
             },
+            "MOV" | "FIL" => {
+                if tokens.len() < 2 {
+                    return Err(format!("Line {}: Missing operand for {}", line_num, instruction));
+                }
+                let opcode = if instruction == "MOV" { 0x12 } else { 0x32 };
+                compile_block(&mut binary, opcode, &instruction, &operand, line_num)?;
+            },
             _ => {
                 return Err(format!("Line {}: Unknown instruction: {}", line_num, instruction));
             }
         }
 
         // Update current address
-        current_address += binary.len() as u16;
+        current_address = current_address.wrapping_add((binary.len() - start) as u16);
+        check_bank_end(bank, binary.len() - bank_offset, line_num)?;
     }
 
-    // Resolve unresolved jumps
-    for (position, label, size) in unresolved_jumps {
-        if let Some(&address) = labels.get(&label) {
-            if size == 1 {
-                // Relative branch
-                let target_address = position as u16 + 1;
-                let offset = (address as i32 - target_address as i32) as i8;
-                binary[position] = offset as u8;
-            } else {
-                // Absolute address (JMP/JSR)
-                binary[position] = (address & 0xFF) as u8;
-                binary[position + 1] = (address >> 8) as u8;
+    Ok(binary)
+}
+
+// Splits `source` into lines, splicing in `.include`d files. `path` is where the
+// source came from, if anywhere. `sites` lists where each file currently being
+// read was included from and `open_files` those files, to catch include cycles.
+fn read_lines(
+    source: &str,
+    path: Option<&Path>,
+    sites: &mut Vec<String>,
+    open_files: &mut Vec<PathBuf>,
+    lines: &mut Vec<SourceLine>
+) -> Result<(), String> {
+    // e.g. "in sprites.asm, included from main.asm line 3"
+    let origin = match path {
+        Some(path) if !sites.is_empty() => {
+            let chain: Vec<String> = sites.iter().rev().map(|site| format!("included from {}", site)).collect();
+            format!("in {}, {}", path.display(), chain.join(", "))
+        },
+        _ => String::new(),
+    };
+
+    for (line_num, line) in source.lines().enumerate() {
+        let line_num = line_num + 1; // 1-based line numbering
+        let line = strip_comment(line).trim(); // Drop trailing comments
+        let source_line = SourceLine { text: line.to_string(), line_num, origin: origin.clone(), macros: Vec::new() };
+        let (first, rest) = split_first_word(line);
+        let include = first.eq_ignore_ascii_case(".include");
+        if !include && !first.eq_ignore_ascii_case(".incbin") {
+            lines.push(source_line);
+            continue;
+        }
+
+        // Both take a path relative to this file
+        let name = parse_path(rest).ok_or_else(|| {
+            locate_error(format!("Line {}: Expected a quoted file name: {} {}", line_num, first, rest), &source_line)
+        })?;
+        let include_path = match path.and_then(Path::parent) {
+            Some(directory) => directory.join(name),
+            None => PathBuf::from(name),
+        };
+        if !include {
+            // The file is read when the line is assembled, it just needs the full path
+            let text = format!(".incbin \"{}\"", include_path.display());
+            lines.push(SourceLine { text, ..source_line });
+            continue;
+        }
+
+        let read_error = |err: std::io::Error| {
+            locate_error(format!("Line {}: Can't read {}: {}", line_num, include_path.display(), err), &source_line)
+        };
+        let canonical = include_path.canonicalize().map_err(read_error)?;
+        if open_files.contains(&canonical) {
+            return Err(locate_error(format!("Line {}: {} includes itself", line_num, include_path.display()), &source_line));
+        }
+        let included = fs::read_to_string(&canonical).map_err(read_error)?;
+
+        sites.push(match path {
+            Some(path) => format!("{} line {}", path.display(), line_num),
+            None => format!("line {}", line_num),
+        });
+        open_files.push(canonical);
+        read_lines(&included, Some(&include_path), sites, open_files, lines)?;
+        open_files.pop();
+        sites.pop();
+    }
+    Ok(())
+}
+
+// Collects `.macro` definitions and replaces every call with the macro's body.
+// Macros can be called before they're defined and can call other macros.
+fn expand_macros(source: Vec<SourceLine>) -> Result<Vec<SourceLine>, String> {
+    let mut macros = HashMap::new();
+    let mut definition: Option<(SourceLine, String, Macro)> = None; // Macro being defined and where
+    let mut top_level = Vec::new();
+
+    for line in source {
+        let line_num = line.line_num;
+        let (first, rest) = split_first_word(&line.text);
+
+        // Checked before anything is renamed, so the names the assembler makes up
+        // for macro and anonymous labels can't clash with one from the source
+        if let Some(label) = line.text.strip_suffix(':') {
+            let label = label.trim();
+            let local = label.strip_prefix('.').unwrap_or(label);
+            if !(label == "+" || label == "-" || is_symbol_name(local)) {
+                return Err(locate_error(format!("Line {}: Invalid label name: {}", line_num, label), &line));
+            }
+        } else if let Some((name, _)) = parse_constant(&line.text) {
+            if !is_symbol_name(name) {
+                return Err(locate_error(format!("Line {}: Invalid constant name: {}", line_num, name), &line));
+            }
+        }
+
+        if first.eq_ignore_ascii_case(".macro") {
+            if let Some((start, name, _)) = &definition {
+                return Err(locate_error(format!("Line {}: Macros can't be defined inside other macros ({} starts on line {})", line_num, name, start.line_num), &line));
+            }
+            // .macro name param1, param2, ...
+            let (name, params) = split_first_word(rest);
+            if !is_symbol_name(name) {
+                return Err(locate_error(format!("Line {}: Invalid macro name: {}", line_num, name), &line));
+            }
+            if macros.contains_key(&name.to_lowercase()) {
+                return Err(locate_error(format!("Line {}: Macro {} is already defined", line_num, name), &line));
             }
+            let params: Vec<String> = split_arguments(params).into_iter().filter(|param| !param.is_empty()).map(String::from).collect();
+            if let Some(param) = params.iter().find(|param| !is_symbol_name(param)) {
+                return Err(locate_error(format!("Line {}: Invalid macro parameter name: {}", line_num, param), &line));
+            }
+            let name = name.to_string();
+            definition = Some((line, name, Macro { params, body: Vec::new() }));
+        } else if first.eq_ignore_ascii_case(".endm") {
+            match definition.take() {
+                Some((_, name, definition)) => {
+                    macros.insert(name.to_lowercase(), definition);
+                },
+                None => return Err(locate_error(format!("Line {}: .endm without a .macro", line_num), &line)),
+            }
+        } else if let Some((_, _, definition)) = &mut definition {
+            definition.body.push(line.text);
         } else {
-            return Err(format!("Undefined label: {}", label));
+            top_level.push(line);
         }
     }
+    if let Some((start, name, _)) = definition {
+        return Err(locate_error(format!("Line {}: Macro {} is missing its .endm", start.line_num, name), &start));
+    }
 
-    Ok(binary)
+    let mut lines = Vec::new();
+    let mut expansions = 0;
+    for line in top_level {
+        expand_line(line, &macros, &mut Vec::new(), &mut expansions, &mut lines)?;
+    }
+    Ok(lines)
+}
+
+// Adds `line` to `lines`, expanding it first if it calls a macro. `callers` holds
+// the macros currently being expanded and `expansions` counts every expansion so
+// far, which keeps the labels of each one apart.
+fn expand_line(
+    mut line: SourceLine,
+    macros: &HashMap<String, Macro>,
+    callers: &mut Vec<String>,
+    expansions: &mut usize,
+    lines: &mut Vec<SourceLine>
+) -> Result<(), String> {
+    line.macros = callers.clone();
+    let (name, args) = split_first_word(&line.text);
+    let Some(definition) = macros.get(&name.to_lowercase()) else {
+        lines.push(line);
+        return Ok(());
+    };
+
+    let line_num = line.line_num;
+    if callers.len() >= MAX_MACRO_DEPTH {
+        return Err(locate_error(format!("Line {}: Macros nested more than {} deep, does {} call itself?", line_num, MAX_MACRO_DEPTH, name), &line));
+    }
+    let args = if args.is_empty() { Vec::new() } else { split_macro_arguments(args) };
+    if args.len() != definition.params.len() {
+        return Err(locate_error(format!("Line {}: Macro {} takes {} arguments but got {}", line_num, name, definition.params.len(), args.len()), &line));
+    }
+
+    // Parameters become the arguments, and labels and constants defined in the
+    // body get a new name each expansion so calling the macro twice doesn't clash
+    *expansions += 1;
+    let mut substitutions: HashMap<&str, String> = definition.params.iter()
+        .map(String::as_str)
+        .zip(args)
+        .collect();
+    for body_line in &definition.body {
+        let symbol = match parse_constant(body_line) {
+            Some((symbol, _)) => symbol,
+            None => match body_line.strip_suffix(':') {
+                Some(label) => label.trim(),
+                None => continue,
+            },
+        };
+        substitutions.insert(symbol, macro_local_name(symbol, *expansions));
+    }
+
+    callers.push(name.to_string());
+    for body_line in &definition.body {
+        let text = substitute_identifiers(body_line, &|word| substitutions.get(word).cloned());
+        expand_line(SourceLine { text, ..line.clone() }, macros, callers, expansions, lines)?;
+    }
+    callers.pop();
+    Ok(())
+}
+
+// The name a label or constant defined in a macro gets in its n-th expansion.
+// A symbol name can't contain a dot followed by a digit, so this can't clash
+// with anything written in the source.
+fn macro_local_name(symbol: &str, n: usize) -> String {
+    format!("{}.{}", symbol, n)
+}
+
+fn is_macro_local_name(name: &str) -> bool {
+    match name.rsplit_once('.') {
+        Some((symbol, n)) => is_symbol_name(symbol) && !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+// Gives local and anonymous labels names that are unique in the whole program.
+// A local label (`.loop`) belongs to the global label before it and becomes
+// `global.loop`, so every subroutine can have its own `.loop`. Anonymous labels
+// are a `+` or `-` on a line of their own; `BNE -` branches back to the nearest
+// `-` and `BNE +` forward to the nearest `+` (`--` and `++` skip one more).
+fn resolve_local_labels(lines: &mut [SourceLine]) -> Result<(), String> {
+    // Anonymous labels become numbered ones
+    let mut anonymous = Vec::new(); // Line index and direction of each anonymous label
+    for (index, line) in lines.iter_mut().enumerate() {
+        let text = line.text.strip_suffix(':').unwrap_or(&line.text).trim();
+        if text == "+" || text == "-" {
+            let forward = text == "+";
+            line.text = format!("{}:", anonymous_label(anonymous.len()));
+            anonymous.push((index, forward));
+        }
+    }
+
+    let mut scope = String::new(); // The last global label
+    for (index, line) in lines.iter_mut().enumerate() {
+        if let Some(label) = line.text.strip_suffix(':') {
+            let label = label.trim();
+            if let Some(local) = label.strip_prefix('.') {
+                line.text = format!("{}.{}:", scope, local);
+            } else if line.macros.is_empty() && !label.starts_with(ANONYMOUS_PREFIX) {
+                // Labels from macros are renamed per call and can't be referred to, so they don't open a scope
+                scope = label.to_string();
+            }
+            continue;
+        }
+
+        let (first, rest) = split_first_word(&line.text);
+
+        // A whole operand of +'s or -'s refers to an anonymous label
+        let operand: String = rest.split_whitespace().collect();
+        let count = operand.len();
+        if count > 0 && (operand.chars().all(|c| c == '+') || operand.chars().all(|c| c == '-')) {
+            let forward = operand.starts_with('+');
+            // ++, +++, ... skip past the nearer ones
+            let target = if forward {
+                anonymous.iter().enumerate()
+                    .filter(|&(_, &(label_index, label_forward))| label_forward && label_index > index)
+                    .nth(count - 1)
+            } else {
+                anonymous.iter().enumerate().rev()
+                    .filter(|&(_, &(label_index, label_forward))| !label_forward && label_index < index)
+                    .nth(count - 1)
+            };
+            let Some((n, _)) = target else {
+                return Err(locate_error(format!("Line {}: No anonymous label for {} to refer to", line.line_num, operand), line));
+            };
+            line.text = format!("{} {}", first, anonymous_label(n));
+            continue;
+        }
+
+        // References to local labels are to the ones in the current scope. The first
+        // word is the instruction or directive, which may start with a dot itself.
+        if rest.contains('.') {
+            let rest = substitute_identifiers(rest, &|word| {
+                word.strip_prefix('.').filter(|local| !local.is_empty()).map(|local| format!("{}.{}", scope, local))
+            });
+            line.text = format!("{} {}", first, rest);
+        }
+    }
+    Ok(())
+}
+
+// The name the n-th anonymous label is given
+fn anonymous_label(n: usize) -> String {
+    format!("{}{}", ANONYMOUS_PREFIX, n)
+}
+
+// Splits a line into its first word and the (trimmed) rest
+fn split_first_word(line: &str) -> (&str, &str) {
+    match line.find(char::is_whitespace) {
+        Some(index) => (&line[..index], line[index..].trim()),
+        None => (line, ""),
+    }
+}
+
+// Takes the file name out of the quotes around it. Escapes aren't supported, so
+// Windows paths can be written as they are.
+fn parse_path(value: &str) -> Option<&str> {
+    let path = value.strip_prefix('"')?.strip_suffix('"')?;
+    if path.is_empty() || path.contains('"') {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+// Replaces the identifiers in `line` that `substitute` gives a replacement for. Numbers,
+// strings and character literals are left alone, so a parameter named `AB` doesn't touch `$AB`.
+fn substitute_identifiers(line: &str, substitute: &dyn Fn(&str) -> Option<String>) -> String {
+    let mut result = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                // Copy up to the closing quote
+                result.push(c);
+                let mut escaped = false;
+                for next in chars.by_ref() {
+                    result.push(next);
+                    if escaped {
+                        escaped = false;
+                    } else if next == '\\' && c == '"' {
+                        escaped = true;
+                    } else if next == c {
+                        break;
+                    }
+                }
+            },
+            c if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$' || c == '%' => {
+                let mut word = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if !(next.is_ascii_alphanumeric() || next == '_' || next == '.') {
+                        break;
+                    }
+                    word.push(next);
+                    chars.next();
+                }
+                match substitute(&word) {
+                    Some(replacement) => result.push_str(&replacement),
+                    None => result.push_str(&word),
+                }
+            },
+            _ => result.push(c),
+        }
+    }
+    result
 }
 
 fn get_instruction_size(instr: &str, operand: &str) -> Result<u16, String> {
     let instr = instr.to_uppercase();
     
     // Branch instructions are always 2 bytes
-    if ["BEQ", "BNE", "BCS", "BCC", "BMI", "BPL"].contains(&instr.as_str()) {
+    if ["BEQ", "BNE", "BCS", "BCC", "BMI", "BPL", "BVS", "BVC"].contains(&instr.as_str()) {
+        return Ok(2);
+    }
+    
+    // Custom instructions that only take a zero page address or an immediate byte
+    if ["DBG", "SND", "MOV", "FIL"].contains(&instr.as_str()) {
         return Ok(2);
     }
     
@@ -359,23 +884,368 @@ fn get_instruction_size(instr: &str, operand: &str) -> Result<u16, String> {
         let parts: Vec<&str> = operand.split(',').collect();
         let addr_part = parts[0].trim();
         
-        if addr_part.starts_with('$') {
-            let is_zp = addr_part.len() <= 3; // $XX (ZP) vs $XXXX (Absolute)
-            Ok(if is_zp { 2 } else { 3 })
-        } else {
-            // Assume it's a label, which will be absolute (3 bytes)
+        // Only LDX and STX have a zero page,Y mode, everything else uses absolute,Y
+        let y_indexed = parts.get(1).is_some_and(|index| index.trim().eq_ignore_ascii_case("Y"));
+        if y_indexed && instr != "LDX" && instr != "STX" {
             Ok(3)
+        } else {
+            Ok(if is_zero_page(addr_part) { 2 } else { 3 })
         }
     } else if operand.starts_with('$') {
         // Direct addressing: depends on length of operand
-        let is_zp = operand.len() <= 3; // $XX (ZP) vs $XXXX (Absolute)
-        Ok(if is_zp { 2 } else { 3 })
+        Ok(if is_zero_page(operand) { 2 } else { 3 })
     } else {
         // Assume it's a label, which will be absolute (3 bytes)
         Ok(3)
     }
 }
 
+// Rewrites an operand's expression as a plain literal the instruction compilers
+// understand, e.g. "SCREEN+32*ROW,X" becomes "$F040,X". Returns the new operand and
+// whether it was written as a zero page address. The first pass passes `zero_page`
+// as None: symbols that aren't defined yet read as an absolute 0, and the size
+// chosen is reported back. Later passes pass in that choice so the size can't change.
+fn resolve_operand(
+    instruction: &str,
+    operand: &str,
+    line_num: usize,
+    current_address: u16,
+    lookup: &dyn Fn(&str) -> Option<Value>,
+    zero_page: Option<bool>
+) -> Result<(String, bool), String> {
+    let instruction = instruction.to_uppercase();
+    let upper = operand.to_uppercase();
+    
+    // Split off the addressing mode syntax around the expression
+    let (prefix, expr, suffix) = if let Some(expr) = operand.strip_prefix('#') {
+        ("#", expr, "")
+    } else if operand.starts_with('(') && upper.ends_with("),Y") {
+        ("(", &operand[1..operand.len() - 3], "),Y")
+    } else if operand.starts_with('(') && upper.ends_with(",X)") {
+        ("(", &operand[1..operand.len() - 3], ",X)")
+    } else if instruction == "JMP" && operand.starts_with('(') && operand.ends_with(')') {
+        ("(", &operand[1..operand.len() - 1], ")")
+    } else if upper.ends_with(",X") {
+        ("", &operand[..operand.len() - 2], ",X")
+    } else if upper.ends_with(",Y") {
+        ("", &operand[..operand.len() - 2], ",Y")
+    } else {
+        ("", operand, "")
+    };
+    
+    let value = match evaluate(expr, line_num, current_address, lookup)? {
+        Evaluation::Known(value) => value,
+        Evaluation::Undefined(_) if zero_page.is_none() => Value::default(),
+        Evaluation::Undefined(name) => return Err(format!("Line {}: Undefined symbol: {}", line_num, name)),
+    };
+    let zero_page = zero_page.unwrap_or(value.zero_page);
+    
+    // Jumps and branches always take a full address, immediates and the indirect
+    // modes a single byte. Everything else is zero page only if the first pass said so.
+    let word_only = ["JMP", "JSR", "BEQ", "BNE", "BCS", "BCC", "BMI", "BPL", "BVS", "BVC"].contains(&instruction.as_str());
+    let byte_only = prefix == "#" || suffix == "),Y" || suffix == ",X)"
+        || ["DBG", "SND", "MOV", "FIL"].contains(&instruction.as_str());
+    if byte_only && value.negative && value.byte().is_none() {
+        return Err(format!("Line {}: Value {} does not fit in a byte", line_num, expr.trim()));
+    }
+    // Bytes may be negative, e.g. "LDA #-1"
+    let literal = match value.byte() {
+        Some(byte) if !word_only && (byte_only || zero_page) => format!("${:02X}", byte),
+        _ => format!("${:04X}", value.value),
+    };
+    Ok((format!("{}{}{}", prefix, literal, suffix), zero_page))
+}
+
+// Looks a name up among the constants and labels defined so far
+fn lookup_symbol(name: &str, labels: &HashMap<String, u16>, constants: &HashMap<String, Value>) -> Option<Value> {
+    constants.get(name).copied().or_else(|| labels.get(name).map(|&value| Value { value, ..Value::default() }))
+}
+
+// Splits a constant definition (`NAME = value`, `NAME .equ value` or
+// `.equ NAME, value`) into its name and value. Returns None for other lines.
+fn parse_constant(line: &str) -> Option<(&str, &str)> {
+    let (name, value) = if let Some((name, value)) = line.split_once('=') {
+        (name, value)
+    } else {
+        let (first, rest) = line.split_once(char::is_whitespace)?;
+        let rest = rest.trim();
+        if first.eq_ignore_ascii_case(".equ") {
+            rest.split_once(',').or_else(|| rest.split_once(char::is_whitespace))?
+        } else {
+            let (directive, value) = rest.split_once(char::is_whitespace)?;
+            if !directive.eq_ignore_ascii_case(".equ") {
+                return None;
+            }
+            (first, value)
+        }
+    };
+    let name = name.trim();
+    if is_symbol_name(name) || is_macro_local_name(name) {
+        Some((name, value.trim()))
+    } else {
+        None
+    }
+}
+
+// What a directive line does to the output
+enum Directive {
+    Bank(u8),      // .bank n
+    Org(u16),      // .org address
+    Data(Vec<u8>), // .byte, .word, .fill, .align, .text/.ascii, .incbin
+}
+
+// Parses a directive line, returning None if the line isn't one. Data values that
+// use symbols not defined yet read as 0 until `resolve` is set on the final pass.
+fn parse_directive(
+    line: &str,
+    line_num: usize,
+    current_address: u16,
+    lookup: &dyn Fn(&str) -> Option<Value>,
+    resolve: bool
+) -> Result<Option<Directive>, String> {
+    if !line.starts_with('.') {
+        return Ok(None);
+    }
+    
+    let (name, args) = split_first_word(line);
+    let name = name.to_lowercase();
+    if args.is_empty() {
+        return Err(format!("Line {}: Missing operand for {}", line_num, name));
+    }
+    let values = split_arguments(args);
+    
+    // Values that move code around have to be known on the first pass
+    let layout_value = |value: &str| match evaluate(value, line_num, current_address, lookup)? {
+        Evaluation::Known(value) => Ok(value.value),
+        Evaluation::Undefined(symbol) => Err(format!("Line {}: {} has to be defined before {} can use it", line_num, symbol, name)),
+    };
+    let data_value = |value: &str| match evaluate(value, line_num, current_address, lookup)? {
+        Evaluation::Known(value) => Ok(value),
+        Evaluation::Undefined(symbol) if resolve => Err(format!("Line {}: Undefined symbol: {}", line_num, symbol)),
+        Evaluation::Undefined(_) => Ok(Value::default()),
+    };
+    let data_byte = |value: &str| {
+        data_value(value)?.byte()
+            .ok_or_else(|| format!("Line {}: Value {} does not fit in a byte", line_num, value))
+    };
+    
+    let directive = match name.as_str() {
+        ".bank" => {
+            let bank = layout_value(args)?;
+            if bank > 0xFF {
+                return Err(format!("Line {}: Bank number {} is too large (max 255)", line_num, bank));
+            }
+            Directive::Bank(bank as u8)
+        },
+        ".org" => Directive::Org(layout_value(args)?),
+        ".byte" => {
+            let mut bytes = Vec::new();
+            for value in values {
+                if value.starts_with('"') {
+                    bytes.extend(parse_string(value, line_num)?);
+                } else {
+                    bytes.push(data_byte(value)?);
+                }
+            }
+            Directive::Data(bytes)
+        },
+        ".word" => {
+            let mut bytes = Vec::new();
+            for value in values {
+                let word = data_value(value)?.value;
+                bytes.push(word as u8);
+                bytes.push((word >> 8) as u8);
+            }
+            Directive::Data(bytes)
+        },
+        ".fill" => {
+            // .fill count[, value]
+            let count = layout_value(values[0])?;
+            let value = match values.get(1) {
+                Some(value) => data_byte(value)?,
+                None => 0,
+            };
+            Directive::Data(vec![value; count as usize])
+        },
+        ".align" => {
+            // .align boundary[, value]: pad to the next multiple of boundary
+            let boundary = layout_value(values[0])? as usize;
+            if boundary == 0 {
+                return Err(format!("Line {}: .align boundary must be at least 1", line_num));
+            }
+            let value = match values.get(1) {
+                Some(value) => data_byte(value)?,
+                None => 0,
+            };
+            let padding = (boundary - current_address as usize % boundary) % boundary;
+            Directive::Data(vec![value; padding])
+        },
+        ".text" | ".ascii" => Directive::Data(parse_string(args, line_num)?),
+        ".incbin" => {
+            // The path was made relative to the including file when the source was read
+            let path = parse_path(args).ok_or_else(|| format!("Line {}: Expected a quoted file name: {}", line_num, args))?;
+            let bytes = fs::read(path).map_err(|err| format!("Line {}: Can't read {}: {}", line_num, path, err))?;
+            Directive::Data(bytes)
+        },
+        ".equ" => return Err(format!("Line {}: Expected a constant definition: .equ NAME, value", line_num)),
+        _ => return Err(format!("Line {}: Unknown directive: {}", line_num, name)),
+    };
+    Ok(Some(directive))
+}
+
+// Drops a trailing `;` comment, leaving semicolons inside quotes alone
+fn strip_comment(line: &str) -> &str {
+    let mut quotes = Quotes::default();
+    for (index, c) in line.char_indices() {
+        if quotes.outside(c) && c == ';' {
+            return &line[..index];
+        }
+    }
+    line
+}
+
+// Splits directive and macro arguments on commas outside of quotes and parentheses
+fn split_arguments(args: &str) -> Vec<&str> {
+    let mut values = Vec::new();
+    let mut quotes = Quotes::default();
+    let mut depth = 0; // Commas inside parentheses, as in ($10,X), don't split
+    let mut start = 0;
+    for (index, c) in args.char_indices() {
+        if !quotes.outside(c) {
+            continue;
+        }
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth <= 0 => {
+                values.push(args[start..index].trim());
+                start = index + 1;
+            },
+            _ => {},
+        }
+    }
+    values.push(args[start..].trim());
+    values
+}
+
+// Splits the arguments of a macro call. An X or Y on its own is the index register
+// of the argument before it, so `($10),Y` and `$10,X` are single arguments.
+fn split_macro_arguments(args: &str) -> Vec<String> {
+    let mut values: Vec<String> = Vec::new();
+    for value in split_arguments(args) {
+        match values.last_mut() {
+            Some(last) if value.eq_ignore_ascii_case("X") || value.eq_ignore_ascii_case("Y") => {
+                last.push(',');
+                last.push_str(value);
+            },
+            _ => values.push(value.to_string()),
+        }
+    }
+    values
+}
+
+// Drops the whitespace from an operand, keeping it inside quotes, so "$F000, Y"
+// reads as "$F000,Y" but ' ' stays a space
+fn compact_operand(operand: &str) -> String {
+    let mut quotes = Quotes::default();
+    operand.chars().filter(|&c| !(quotes.outside(c) && c.is_whitespace())).collect()
+}
+
+// Follows "strings" (with \ escapes) and 'c' character literals while scanning a line
+#[derive(Default)]
+struct Quotes {
+    quote: Option<char>, // The quote that opened the string or literal we're in
+    escaped: bool,
+}
+
+impl Quotes {
+    // Feeds in the next character, returning whether it's outside of any quotes.
+    // Quote characters themselves don't count as outside.
+    fn outside(&mut self, c: char) -> bool {
+        match self.quote {
+            Some(_) if self.escaped => self.escaped = false,
+            Some('"') if c == '\\' => self.escaped = true,
+            Some(quote) if c == quote => self.quote = None,
+            Some(_) => {},
+            None if c == '"' || c == '\'' => self.quote = Some(c),
+            None => return true,
+        }
+        false
+    }
+}
+
+// Parses a quoted string into its bytes. Supports \n, \r, \t, \0, \" and \\.
+fn parse_string(value: &str, line_num: usize) -> Result<Vec<u8>, String> {
+    if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+        return Err(format!("Line {}: Expected a quoted string: {}", line_num, value));
+    }
+    
+    let mut bytes = Vec::new();
+    let mut chars = value[1..value.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        let c = if c == '\\' {
+            match chars.next() {
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some('"') => '"',
+                Some('\\') => '\\',
+                other => return Err(format!("Line {}: Unknown escape sequence \\{} in string", line_num, other.map(String::from).unwrap_or_default())),
+            }
+        } else {
+            c
+        };
+        if !c.is_ascii() {
+            return Err(format!("Line {}: Only ASCII characters are allowed in strings: {}", line_num, c));
+        }
+        bytes.push(c as u8);
+    }
+    Ok(bytes)
+}
+
+// Where a bank goes in the ROM image and the address its code runs at.
+// Banks 0 and 1 are the first 32KB; banks 2 and up follow the 64KB address
+// space image and all run in the $4000 window.
+fn bank_location(bank: u8) -> (usize, u16) {
+    match bank {
+        0 => (0, 0),
+        1 => (BANK_WINDOW_START, BANK_WINDOW_START as u16),
+        _ => (EXTRA_BANKS_START + (bank as usize - 2) * BANK_SIZE, BANK_WINDOW_START as u16),
+    }
+}
+
+// Addresses switchable banks run at
+fn bank_window() -> std::ops::Range<usize> {
+    BANK_WINDOW_START..BANK_WINDOW_START + BANK_SIZE
+}
+
+// Fails once code in a switchable bank reaches `end`, the address after its last byte, past $7FFF
+fn check_bank_end(bank: Option<u8>, end: usize, line_num: usize) -> Result<(), String> {
+    match bank {
+        Some(bank) if end > bank_window().end => {
+            Err(format!("Line {}: Bank {} runs past $7FFF", line_num, bank))
+        },
+        _ => Ok(()),
+    }
+}
+
+// Offset of a branch at `address` to `target`, relative to the following instruction
+fn branch_offset(target: u16, address: u16, line_num: usize) -> Result<i8, String> {
+    let offset = target as i32 - (address as i32 + 2);
+    if offset < i8::MIN as i32 || offset > i8::MAX as i32 {
+        return Err(format!("Line {}: Branch target ${:04X} is out of range ({} bytes away)", line_num, target, offset));
+    }
+    Ok(offset as i8)
+}
+
+// Whether an operand literal means a zero page address: $XX is zero page, $00XX
+// and anything else is absolute. Expressions are rewritten to one or the other
+// by `resolve_operand` before this is asked.
+fn is_zero_page(value_str: &str) -> bool {
+    value_str.starts_with('$') && value_str.len() <= 3
+}
+
 fn parse_value(value_str: &str, line_num: usize) -> Result<u16, String> {
     if value_str.starts_with('$') {
         // Hexadecimal
@@ -412,10 +1282,7 @@ fn parse_and_push_value(binary: &mut Vec<u8>, value_str: &str, num_bytes: usize,
 
 fn compile_lda(
     binary: &mut Vec<u8>,
-    unresolved_jumps: &mut Vec<(usize, String, usize)>,
     operand: &str,
-    current_address: u16,
-    labels: &HashMap<String, u16>,
     line_num: usize
 ) -> Result<(), String> {
     if operand.starts_with('#') {
@@ -442,70 +1309,35 @@ fn compile_lda(
         let addr_part = parts[0].trim();
         let index_part = parts[1].trim().to_uppercase();
         
-        let addr_value = if addr_part.starts_with('$') {
-            // Parse address value
-            Some(parse_value(addr_part, line_num)?)
-        } else {
-            None // Label
-        };
+        let addr_value = parse_value(addr_part, line_num)?;
         
         if index_part == "X" {
-            match addr_value {
-                Some(addr) if addr <= 0xFF => {
-                    // Zero Page,X
-                    binary.push(0xB5);
-                    binary.push(addr as u8);
-                },
-                Some(_) => {
-                    // Absolute,X
-                    binary.push(0xBD);
-                    parse_and_push_value(binary, addr_part, 2, line_num)?;
-                },
-                None => {
-                    // Label,X (assume absolute)
-                    binary.push(0xBD);
-                    if let Some(&address) = labels.get(addr_part) {
-                        binary.push((address & 0xFF) as u8);
-                        binary.push((address >> 8) as u8);
-                    } else {
-                        unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                        binary.push(0);
-                        binary.push(0);
-                    }
-                }
+            if is_zero_page(addr_part) {
+                // Zero Page,X
+                binary.push(0xB5);
+                binary.push(addr_value as u8);
+            } else {
+                // Absolute,X
+                binary.push(0xBD);
+                parse_and_push_value(binary, addr_part, 2, line_num)?;
             }
         } else if index_part == "Y" {
-            match addr_value {
-                Some(addr) if addr <= 0xFF => {
-                    // No Zero Page,Y for LDA, use Absolute,Y
-                    binary.push(0xB9);
-                    parse_and_push_value(binary, addr_part, 2, line_num)?;
-                },
-                Some(_) => {
-                    // Absolute,Y
-                    binary.push(0xB9);
-                    parse_and_push_value(binary, addr_part, 2, line_num)?;
-                },
-                None => {
-                    // Label,Y (assume absolute)
-                    binary.push(0xB9);
-                    if let Some(&address) = labels.get(addr_part) {
-                        binary.push((address & 0xFF) as u8);
-                        binary.push((address >> 8) as u8);
-                    } else {
-                        unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                        binary.push(0);
-                        binary.push(0);
-                    }
-                }
+            if is_zero_page(addr_part) {
+                // No Zero Page,Y for LDA, use Absolute,Y
+                binary.push(0xB9);
+                parse_and_push_value(binary, addr_part, 2, line_num)?;
+            } else {
+                // Absolute,Y
+                binary.push(0xB9);
+                parse_and_push_value(binary, addr_part, 2, line_num)?;
             }
         } else {
             return Err(format!("Line {}: Invalid index register: {}", line_num, index_part));
         }
-    } else if operand.starts_with('$') {
+    } else {
         // Zero Page or Absolute
         let value = parse_value(operand, line_num)?;
-        if value <= 0xFF {
+        if is_zero_page(operand) {
             // Zero Page
             binary.push(0xA5);
             binary.push(value as u8);
@@ -515,17 +1347,6 @@ fn compile_lda(
             binary.push((value & 0xFF) as u8);
             binary.push((value >> 8) as u8);
         }
-    } else {
-        // Assume it's a label (Absolute)
-        binary.push(0xAD);
-        if let Some(&address) = labels.get(operand) {
-            binary.push((address & 0xFF) as u8);
-            binary.push((address >> 8) as u8);
-        } else {
-            unresolved_jumps.push((binary.len(), operand.to_string(), 2));
-            binary.push(0);
-            binary.push(0);
-        }
     }
     
     Ok(())
@@ -533,10 +1354,7 @@ fn compile_lda(
 
 fn compile_ldx(
     binary: &mut Vec<u8>,
-    unresolved_jumps: &mut Vec<(usize, String, usize)>,
     operand: &str,
-    current_address: u16,
-    labels: &HashMap<String, u16>,
     line_num: usize
 ) -> Result<(), String> {
     if operand.starts_with('#') {
@@ -557,41 +1375,21 @@ fn compile_ldx(
             return Err(format!("Line {}: LDX only supports Y-indexed addressing, got: {}", line_num, index_part));
         }
         
-        let addr_value = if addr_part.starts_with('$') {
-            // Parse address value
-            Some(parse_value(addr_part, line_num)?)
-        } else {
-            None // Label
-        };
+        let addr_value = parse_value(addr_part, line_num)?;
         
-        match addr_value {
-            Some(addr) if addr <= 0xFF => {
-                // Zero Page,Y
-                binary.push(0xB6);
-                binary.push(addr as u8);
-            },
-            Some(_) => {
-                // Absolute,Y
-                binary.push(0xBE);
-                parse_and_push_value(binary, addr_part, 2, line_num)?;
-            },
-            None => {
-                // Label,Y (assume absolute)
-                binary.push(0xBE);
-                if let Some(&address) = labels.get(addr_part) {
-                    binary.push((address & 0xFF) as u8);
-                    binary.push((address >> 8) as u8);
-                } else {
-                    unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                    binary.push(0);
-                    binary.push(0);
-                }
-            }
+        if is_zero_page(addr_part) {
+            // Zero Page,Y
+            binary.push(0xB6);
+            binary.push(addr_value as u8);
+        } else {
+            // Absolute,Y
+            binary.push(0xBE);
+            parse_and_push_value(binary, addr_part, 2, line_num)?;
         }
-    } else if operand.starts_with('$') {
+    } else {
         // Zero Page or Absolute
         let value = parse_value(operand, line_num)?;
-        if value <= 0xFF {
+        if is_zero_page(operand) {
             // Zero Page
             binary.push(0xA6);
             binary.push(value as u8);
@@ -601,17 +1399,6 @@ fn compile_ldx(
             binary.push((value & 0xFF) as u8);
             binary.push((value >> 8) as u8);
         }
-    } else {
-        // Assume it's a label (Absolute)
-        binary.push(0xAE);
-        if let Some(&address) = labels.get(operand) {
-            binary.push((address & 0xFF) as u8);
-            binary.push((address >> 8) as u8);
-        } else {
-            unresolved_jumps.push((binary.len(), operand.to_string(), 2));
-            binary.push(0);
-            binary.push(0);
-        }
     }
     
     Ok(())
@@ -619,10 +1406,7 @@ fn compile_ldx(
 
 fn compile_ldy(
     binary: &mut Vec<u8>,
-    unresolved_jumps: &mut Vec<(usize, String, usize)>,
     operand: &str,
-    current_address: u16,
-    labels: &HashMap<String, u16>,
     line_num: usize
 ) -> Result<(), String> {
     if operand.starts_with('#') {
@@ -643,41 +1427,21 @@ fn compile_ldy(
             return Err(format!("Line {}: LDY only supports X-indexed addressing, got: {}", line_num, index_part));
         }
         
-        let addr_value = if addr_part.starts_with('$') {
-            // Parse address value
-            Some(parse_value(addr_part, line_num)?)
-        } else {
-            None // Label
-        };
+        let addr_value = parse_value(addr_part, line_num)?;
         
-        match addr_value {
-            Some(addr) if addr <= 0xFF => {
-                // Zero Page,X
-                binary.push(0xB4);
-                binary.push(addr as u8);
-            },
-            Some(_) => {
-                // Absolute,X
-                binary.push(0xBC);
-                parse_and_push_value(binary, addr_part, 2, line_num)?;
-            },
-            None => {
-                // Label,X (assume absolute)
-                binary.push(0xBC);
-                if let Some(&address) = labels.get(addr_part) {
-                    binary.push((address & 0xFF) as u8);
-                    binary.push((address >> 8) as u8);
-                } else {
-                    unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                    binary.push(0);
-                    binary.push(0);
-                }
-            }
+        if is_zero_page(addr_part) {
+            // Zero Page,X
+            binary.push(0xB4);
+            binary.push(addr_value as u8);
+        } else {
+            // Absolute,X
+            binary.push(0xBC);
+            parse_and_push_value(binary, addr_part, 2, line_num)?;
         }
-    } else if operand.starts_with('$') {
+    } else {
         // Zero Page or Absolute
         let value = parse_value(operand, line_num)?;
-        if value <= 0xFF {
+        if is_zero_page(operand) {
             // Zero Page
             binary.push(0xA4);
             binary.push(value as u8);
@@ -687,17 +1451,6 @@ fn compile_ldy(
             binary.push((value & 0xFF) as u8);
             binary.push((value >> 8) as u8);
         }
-    } else {
-        // Assume it's a label (Absolute)
-        binary.push(0xAC);
-        if let Some(&address) = labels.get(operand) {
-            binary.push((address & 0xFF) as u8);
-            binary.push((address >> 8) as u8);
-        } else {
-            unresolved_jumps.push((binary.len(), operand.to_string(), 2));
-            binary.push(0);
-            binary.push(0);
-        }
     }
     
     Ok(())
@@ -705,15 +1458,9 @@ fn compile_ldy(
 
 fn compile_sta(
     binary: &mut Vec<u8>,
-    unresolved_jumps: &mut Vec<(usize, String, usize)>,
-    operands: Vec<&str>,  // Changed from single operand to slice of operands
-    _current_address: u16,
-    labels: &HashMap<String, u16>,
+    operand: &str,
     line_num: usize
 ) -> Result<(), String> {
-    // Assume the first operand is the address/operand
-    let operand = operands[0].split(';').next().unwrap().trim();
-
     if operand.starts_with('#') {
         return Err(format!("Line {}: STA does not support immediate addressing", line_num));
     } else if operand.starts_with('(') && operand.ends_with(",X)") {
@@ -728,89 +1475,41 @@ fn compile_sta(
         parse_and_push_value(binary, addr_part, 1, line_num)?;
     } else if operand.contains(',') {
         // Indexed addressing (Zero Page,X or Absolute,X / Absolute,Y)
-        let parts: Vec<&str> = operand.split(&[',', ' '])
-            .map(|s| s.trim())
-            .filter(|s| !s.is_empty())
-            .collect();
-                
+        let parts: Vec<&str> = operand.split(',').collect();
         if parts.len() != 2 {
             return Err(format!("Line {}: Invalid indexed addressing format: {}", line_num, operand));
         }
         let addr_part = parts[0].trim();
         let index_part = parts[1].trim().to_uppercase();
-        let addr_value = if addr_part.starts_with('$') {
-            Some(parse_value(addr_part, line_num)?)
-        } else {
-            None
-        };
+        let addr_value = parse_value(addr_part, line_num)?;
         if index_part == "X" {
-            match addr_value {
-                Some(addr) if addr <= 0xFF => {
-                    // Zero Page,X
-                    binary.push(0x95);
-                    binary.push(addr as u8);
-                },
-                Some(_) => {
-                    // Absolute,X
-                    binary.push(0x9D);
-                    parse_and_push_value(binary, addr_part, 2, line_num)?;
-                },
-                None => {
-                    binary.push(0x9D);
-                    if let Some(&address) = labels.get(addr_part) {
-                        binary.push((address & 0xFF) as u8);
-                        binary.push((address >> 8) as u8);
-                    } else {
-                        unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                        binary.push(0);
-                        binary.push(0);
-                    }
-                }
+            if is_zero_page(addr_part) {
+                // Zero Page,X
+                binary.push(0x95);
+                binary.push(addr_value as u8);
+            } else {
+                // Absolute,X
+                binary.push(0x9D);
+                parse_and_push_value(binary, addr_part, 2, line_num)?;
             }
         } else if index_part == "Y" {
             // STA does not support a zero page,Y mode; only absolute,Y is allowed.
             binary.push(0x99);
-            match addr_value {
-                Some(addr) => {
-                    binary.push((addr & 0xFF) as u8);
-                    binary.push((addr >> 8) as u8);
-                }
-                None => {
-                    if let Some(&address) = labels.get(addr_part){
-                        binary.push((address & 0xFF) as u8);
-                        binary.push((address >> 8) as u8);
-                    }
-                    else {
-                        unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                        binary.push(0);
-                        binary.push(0);
-                    }
-                }
-            }
+            binary.push((addr_value & 0xFF) as u8);
+            binary.push((addr_value >> 8) as u8);
         } else {
             return Err(format!("Line {}: Invalid index register: {}", line_num, index_part));
         }
-    } else if operand.starts_with('$') {
+    } else {
         // Zero Page or Absolute
         let value = parse_value(operand, line_num)?;
-        if value <= 0xFF {
+        if is_zero_page(operand) {
             binary.push(0x85);
             binary.push(value as u8);
         } else {
             binary.push(0x8D);
-            binary.push((value & 0xFF) as u8);
-            binary.push((value >> 8) as u8);
-        }
-    } else {
-        // Label (Absolute)
-        binary.push(0x8D);
-        if let Some(&address) = labels.get(operand) {
-            binary.push((address & 0xFF) as u8);
-            binary.push((address >> 8) as u8);
-        } else {
-            unresolved_jumps.push((binary.len(), operand.to_string(), 2));
-            binary.push(0);
-            binary.push(0);
+            binary.push((value & 0xFF) as u8);
+            binary.push((value >> 8) as u8);
         }
     }
     Ok(())
@@ -818,10 +1517,7 @@ fn compile_sta(
 
 fn compile_stx(
     binary: &mut Vec<u8>,
-    unresolved_jumps: &mut Vec<(usize, String, usize)>,
     operand: &str,
-    _current_address: u16,
-    labels: &HashMap<String, u16>,
     line_num: usize
 ) -> Result<(), String> {
     if operand.starts_with('#') {
@@ -836,33 +1532,17 @@ fn compile_stx(
         if index_part != "Y" {
             return Err(format!("Line {}: STX only supports Y-indexed addressing", line_num));
         }
-        let addr_value = if addr_part.starts_with('$') {
-            Some(parse_value(addr_part, line_num)?)
+        let addr_value = parse_value(addr_part, line_num)?;
+        if is_zero_page(addr_part) {
+            binary.push(0x96);
+            binary.push(addr_value as u8);
         } else {
-            None
-        };
-        match addr_value {
-            Some(addr) if addr <= 0xFF => {
-                binary.push(0x96);
-                binary.push(addr as u8);
-            },
-            Some(_) => {
-                // Absolute,Y is not defined for STX.
-                return Err(format!("Line {}: STX does not support absolute,Y addressing", line_num));
-            },
-            None => {
-                binary.push(0x96);
-                if let Some(&address) = labels.get(addr_part) {
-                    binary.push((address & 0xFF) as u8);
-                } else {
-                    unresolved_jumps.push((binary.len(), addr_part.to_string(), 1));
-                    binary.push(0);
-                }
-            }
+            // Absolute,Y is not defined for STX.
+            return Err(format!("Line {}: STX does not support absolute,Y addressing", line_num));
         }
-    } else if operand.starts_with('$') {
+    } else {
         let value = parse_value(operand, line_num)?;
-        if value <= 0xFF {
+        if is_zero_page(operand) {
             binary.push(0x86);
             binary.push(value as u8);
         } else {
@@ -870,26 +1550,13 @@ fn compile_stx(
             binary.push((value & 0xFF) as u8);
             binary.push((value >> 8) as u8);
         }
-    } else {
-        binary.push(0x8E);
-        if let Some(&address) = labels.get(operand) {
-            binary.push((address & 0xFF) as u8);
-            binary.push((address >> 8) as u8);
-        } else {
-            unresolved_jumps.push((binary.len(), operand.to_string(), 2));
-            binary.push(0);
-            binary.push(0);
-        }
     }
     Ok(())
 }
 
 fn compile_sty(
     binary: &mut Vec<u8>,
-    unresolved_jumps: &mut Vec<(usize, String, usize)>,
     operand: &str,
-    _current_address: u16,
-    labels: &HashMap<String, u16>,
     line_num: usize
 ) -> Result<(), String> {
     if operand.starts_with('#') {
@@ -904,33 +1571,17 @@ fn compile_sty(
         if index_part != "X" {
             return Err(format!("Line {}: STY only supports X-indexed addressing", line_num));
         }
-        let addr_value = if addr_part.starts_with('$') {
-            Some(parse_value(addr_part, line_num)?)
+        let addr_value = parse_value(addr_part, line_num)?;
+        if is_zero_page(addr_part) {
+            binary.push(0x94);
+            binary.push(addr_value as u8);
         } else {
-            None
-        };
-        match addr_value {
-            Some(addr) if addr <= 0xFF => {
-                binary.push(0x94);
-                binary.push(addr as u8);
-            },
-            Some(_) => {
-                // Absolute,X is not supported for STY.
-                return Err(format!("Line {}: STY does not support absolute,X addressing", line_num));
-            },
-            None => {
-                binary.push(0x94);
-                if let Some(&address) = labels.get(addr_part) {
-                    binary.push((address & 0xFF) as u8);
-                } else {
-                    unresolved_jumps.push((binary.len(), addr_part.to_string(), 1));
-                    binary.push(0);
-                }
-            }
+            // Absolute,X is not supported for STY.
+            return Err(format!("Line {}: STY does not support absolute,X addressing", line_num));
         }
-    } else if operand.starts_with('$') {
+    } else {
         let value = parse_value(operand, line_num)?;
-        if value <= 0xFF {
+        if is_zero_page(operand) {
             binary.push(0x84);
             binary.push(value as u8);
         } else {
@@ -938,26 +1589,13 @@ fn compile_sty(
             binary.push((value & 0xFF) as u8);
             binary.push((value >> 8) as u8);
         }
-    } else {
-        binary.push(0x8C);
-        if let Some(&address) = labels.get(operand) {
-            binary.push((address & 0xFF) as u8);
-            binary.push((address >> 8) as u8);
-        } else {
-            unresolved_jumps.push((binary.len(), operand.to_string(), 2));
-            binary.push(0);
-            binary.push(0);
-        }
     }
     Ok(())
 }
 
 fn compile_adc(
     binary: &mut Vec<u8>,
-    unresolved_jumps: &mut Vec<(usize, String, usize)>,
     operand: &str,
-    _current_address: u16,
-    labels: &HashMap<String, u16>,
     line_num: usize
 ) -> Result<(), String> {
     if operand.starts_with('#') {
@@ -978,57 +1616,24 @@ fn compile_adc(
         }
         let addr_part = parts[0].trim();
         let index_part = parts[1].trim().to_uppercase();
-        let addr_value = if addr_part.starts_with('$') {
-            Some(parse_value(addr_part, line_num)?)
-        } else {
-            None
-        };
+        let addr_value = parse_value(addr_part, line_num)?;
         if index_part == "X" {
-            match addr_value {
-                Some(addr) if addr <= 0xFF => {
-                    binary.push(0x75);
-                    binary.push(addr as u8);
-                },
-                Some(_) => {
-                    binary.push(0x7D);
-                    parse_and_push_value(binary, addr_part, 2, line_num)?;
-                },
-                None => {
-                    binary.push(0x7D);
-                    if let Some(&address) = labels.get(addr_part) {
-                        binary.push((address & 0xFF) as u8);
-                        binary.push((address >> 8) as u8);
-                    } else {
-                        unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                        binary.push(0);
-                        binary.push(0);
-                    }
-                }
+            if is_zero_page(addr_part) {
+                binary.push(0x75);
+                binary.push(addr_value as u8);
+            } else {
+                binary.push(0x7D);
+                parse_and_push_value(binary, addr_part, 2, line_num)?;
             }
         } else if index_part == "Y" {
-            match addr_value {
-                Some(_) => {
-                    binary.push(0x79);
-                    parse_and_push_value(binary, addr_part, 2, line_num)?;
-                },
-                None => {
-                    binary.push(0x79);
-                    if let Some(&address) = labels.get(addr_part) {
-                        binary.push((address & 0xFF) as u8);
-                        binary.push((address >> 8) as u8);
-                    } else {
-                        unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                        binary.push(0);
-                        binary.push(0);
-                    }
-                }
-            }
+            binary.push(0x79);
+            parse_and_push_value(binary, addr_part, 2, line_num)?;
         } else {
             return Err(format!("Line {}: Invalid index register: {}", line_num, index_part));
         }
-    } else if operand.starts_with('$') {
+    } else {
         let value = parse_value(operand, line_num)?;
-        if value <= 0xFF {
+        if is_zero_page(operand) {
             binary.push(0x65);
             binary.push(value as u8);
         } else {
@@ -1036,26 +1641,13 @@ fn compile_adc(
             binary.push((value & 0xFF) as u8);
             binary.push((value >> 8) as u8);
         }
-    } else {
-        binary.push(0x6D);
-        if let Some(&address) = labels.get(operand) {
-            binary.push((address & 0xFF) as u8);
-            binary.push((address >> 8) as u8);
-        } else {
-            unresolved_jumps.push((binary.len(), operand.to_string(), 2));
-            binary.push(0);
-            binary.push(0);
-        }
     }
     Ok(())
 }
 
 fn compile_sbc(
     binary: &mut Vec<u8>,
-    unresolved_jumps: &mut Vec<(usize, String, usize)>,
     operand: &str,
-    _current_address: u16,
-    labels: &HashMap<String, u16>,
     line_num: usize
 ) -> Result<(), String> {
     if operand.starts_with('#') {
@@ -1076,57 +1668,24 @@ fn compile_sbc(
         }
         let addr_part = parts[0].trim();
         let index_part = parts[1].trim().to_uppercase();
-        let addr_value = if addr_part.starts_with('$') {
-            Some(parse_value(addr_part, line_num)?)
-        } else {
-            None
-        };
+        let addr_value = parse_value(addr_part, line_num)?;
         if index_part == "X" {
-            match addr_value {
-                Some(addr) if addr <= 0xFF => {
-                    binary.push(0xF5);
-                    binary.push(addr as u8);
-                },
-                Some(_) => {
-                    binary.push(0xFD);
-                    parse_and_push_value(binary, addr_part, 2, line_num)?;
-                },
-                None => {
-                    binary.push(0xFD);
-                    if let Some(&address) = labels.get(addr_part) {
-                        binary.push((address & 0xFF) as u8);
-                        binary.push((address >> 8) as u8);
-                    } else {
-                        unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                        binary.push(0);
-                        binary.push(0);
-                    }
-                }
+            if is_zero_page(addr_part) {
+                binary.push(0xF5);
+                binary.push(addr_value as u8);
+            } else {
+                binary.push(0xFD);
+                parse_and_push_value(binary, addr_part, 2, line_num)?;
             }
         } else if index_part == "Y" {
-            match addr_value {
-                Some(_) => {
-                    binary.push(0xF9);
-                    parse_and_push_value(binary, addr_part, 2, line_num)?;
-                },
-                None => {
-                    binary.push(0xF9);
-                    if let Some(&address) = labels.get(addr_part) {
-                        binary.push((address & 0xFF) as u8);
-                        binary.push((address >> 8) as u8);
-                    } else {
-                        unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                        binary.push(0);
-                        binary.push(0);
-                    }
-                }
-            }
+            binary.push(0xF9);
+            parse_and_push_value(binary, addr_part, 2, line_num)?;
         } else {
             return Err(format!("Line {}: Invalid index register: {}", line_num, index_part));
         }
-    } else if operand.starts_with('$') {
+    } else {
         let value = parse_value(operand, line_num)?;
-        if value <= 0xFF {
+        if is_zero_page(operand) {
             binary.push(0xE5);
             binary.push(value as u8);
         } else {
@@ -1134,26 +1693,13 @@ fn compile_sbc(
             binary.push((value & 0xFF) as u8);
             binary.push((value >> 8) as u8);
         }
-    } else {
-        binary.push(0xED);
-        if let Some(&address) = labels.get(operand) {
-            binary.push((address & 0xFF) as u8);
-            binary.push((address >> 8) as u8);
-        } else {
-            unresolved_jumps.push((binary.len(), operand.to_string(), 2));
-            binary.push(0);
-            binary.push(0);
-        }
     }
     Ok(())
 }
 
 fn compile_and(
     binary: &mut Vec<u8>,
-    unresolved_jumps: &mut Vec<(usize, String, usize)>,
     operand: &str,
-    _current_address: u16,
-    labels: &HashMap<String, u16>,
     line_num: usize
 ) -> Result<(), String> {
     if operand.starts_with('#') {
@@ -1174,57 +1720,24 @@ fn compile_and(
         }
         let addr_part = parts[0].trim();
         let index_part = parts[1].trim().to_uppercase();
-        let addr_value = if addr_part.starts_with('$') {
-            Some(parse_value(addr_part, line_num)?)
-        } else {
-            None
-        };
+        let addr_value = parse_value(addr_part, line_num)?;
         if index_part == "X" {
-            match addr_value {
-                Some(addr) if addr <= 0xFF => {
-                    binary.push(0x35);
-                    binary.push(addr as u8);
-                },
-                Some(_) => {
-                    binary.push(0x3D);
-                    parse_and_push_value(binary, addr_part, 2, line_num)?;
-                },
-                None => {
-                    binary.push(0x3D);
-                    if let Some(&address) = labels.get(addr_part) {
-                        binary.push((address & 0xFF) as u8);
-                        binary.push((address >> 8) as u8);
-                    } else {
-                        unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                        binary.push(0);
-                        binary.push(0);
-                    }
-                }
+            if is_zero_page(addr_part) {
+                binary.push(0x35);
+                binary.push(addr_value as u8);
+            } else {
+                binary.push(0x3D);
+                parse_and_push_value(binary, addr_part, 2, line_num)?;
             }
         } else if index_part == "Y" {
-            match addr_value {
-                Some(_) => {
-                    binary.push(0x39);
-                    parse_and_push_value(binary, addr_part, 2, line_num)?;
-                },
-                None => {
-                    binary.push(0x39);
-                    if let Some(&address) = labels.get(addr_part) {
-                        binary.push((address & 0xFF) as u8);
-                        binary.push((address >> 8) as u8);
-                    } else {
-                        unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                        binary.push(0);
-                        binary.push(0);
-                    }
-                }
-            }
+            binary.push(0x39);
+            parse_and_push_value(binary, addr_part, 2, line_num)?;
         } else {
             return Err(format!("Line {}: Invalid index register: {}", line_num, index_part));
         }
-    } else if operand.starts_with('$') {
+    } else {
         let value = parse_value(operand, line_num)?;
-        if value <= 0xFF {
+        if is_zero_page(operand) {
             binary.push(0x25);
             binary.push(value as u8);
         } else {
@@ -1232,26 +1745,13 @@ fn compile_and(
             binary.push((value & 0xFF) as u8);
             binary.push((value >> 8) as u8);
         }
-    } else {
-        binary.push(0x2D);
-        if let Some(&address) = labels.get(operand) {
-            binary.push((address & 0xFF) as u8);
-            binary.push((address >> 8) as u8);
-        } else {
-            unresolved_jumps.push((binary.len(), operand.to_string(), 2));
-            binary.push(0);
-            binary.push(0);
-        }
     }
     Ok(())
 }
 
 fn compile_ora(
     binary: &mut Vec<u8>,
-    unresolved_jumps: &mut Vec<(usize, String, usize)>,
     operand: &str,
-    _current_address: u16,
-    labels: &HashMap<String, u16>,
     line_num: usize
 ) -> Result<(), String> {
     if operand.starts_with('#') {
@@ -1272,57 +1772,24 @@ fn compile_ora(
         }
         let addr_part = parts[0].trim();
         let index_part = parts[1].trim().to_uppercase();
-        let addr_value = if addr_part.starts_with('$') {
-            Some(parse_value(addr_part, line_num)?)
-        } else {
-            None
-        };
+        let addr_value = parse_value(addr_part, line_num)?;
         if index_part == "X" {
-            match addr_value {
-                Some(addr) if addr <= 0xFF => {
-                    binary.push(0x15);
-                    binary.push(addr as u8);
-                },
-                Some(_) => {
-                    binary.push(0x1D);
-                    parse_and_push_value(binary, addr_part, 2, line_num)?;
-                },
-                None => {
-                    binary.push(0x1D);
-                    if let Some(&address) = labels.get(addr_part) {
-                        binary.push((address & 0xFF) as u8);
-                        binary.push((address >> 8) as u8);
-                    } else {
-                        unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                        binary.push(0);
-                        binary.push(0);
-                    }
-                }
+            if is_zero_page(addr_part) {
+                binary.push(0x15);
+                binary.push(addr_value as u8);
+            } else {
+                binary.push(0x1D);
+                parse_and_push_value(binary, addr_part, 2, line_num)?;
             }
         } else if index_part == "Y" {
-            match addr_value {
-                Some(_) => {
-                    binary.push(0x19);
-                    parse_and_push_value(binary, addr_part, 2, line_num)?;
-                },
-                None => {
-                    binary.push(0x19);
-                    if let Some(&address) = labels.get(addr_part) {
-                        binary.push((address & 0xFF) as u8);
-                        binary.push((address >> 8) as u8);
-                    } else {
-                        unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                        binary.push(0);
-                        binary.push(0);
-                    }
-                }
-            }
+            binary.push(0x19);
+            parse_and_push_value(binary, addr_part, 2, line_num)?;
         } else {
             return Err(format!("Line {}: Invalid index register: {}", line_num, index_part));
         }
-    } else if operand.starts_with('$') {
+    } else {
         let value = parse_value(operand, line_num)?;
-        if value <= 0xFF {
+        if is_zero_page(operand) {
             binary.push(0x05);
             binary.push(value as u8);
         } else {
@@ -1330,26 +1797,13 @@ fn compile_ora(
             binary.push((value & 0xFF) as u8);
             binary.push((value >> 8) as u8);
         }
-    } else {
-        binary.push(0x0D);
-        if let Some(&address) = labels.get(operand) {
-            binary.push((address & 0xFF) as u8);
-            binary.push((address >> 8) as u8);
-        } else {
-            unresolved_jumps.push((binary.len(), operand.to_string(), 2));
-            binary.push(0);
-            binary.push(0);
-        }
     }
     Ok(())
 }
 
 fn compile_eor(
     binary: &mut Vec<u8>,
-    unresolved_jumps: &mut Vec<(usize, String, usize)>,
     operand: &str,
-    _current_address: u16,
-    labels: &HashMap<String, u16>,
     line_num: usize
 ) -> Result<(), String> {
     if operand.starts_with('#') {
@@ -1370,57 +1824,24 @@ fn compile_eor(
         }
         let addr_part = parts[0].trim();
         let index_part = parts[1].trim().to_uppercase();
-        let addr_value = if addr_part.starts_with('$') {
-            Some(parse_value(addr_part, line_num)?)
-        } else {
-            None
-        };
+        let addr_value = parse_value(addr_part, line_num)?;
         if index_part == "X" {
-            match addr_value {
-                Some(addr) if addr <= 0xFF => {
-                    binary.push(0x55);
-                    binary.push(addr as u8);
-                },
-                Some(_) => {
-                    binary.push(0x5D);
-                    parse_and_push_value(binary, addr_part, 2, line_num)?;
-                },
-                None => {
-                    binary.push(0x5D);
-                    if let Some(&address) = labels.get(addr_part) {
-                        binary.push((address & 0xFF) as u8);
-                        binary.push((address >> 8) as u8);
-                    } else {
-                        unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                        binary.push(0);
-                        binary.push(0);
-                    }
-                }
+            if is_zero_page(addr_part) {
+                binary.push(0x55);
+                binary.push(addr_value as u8);
+            } else {
+                binary.push(0x5D);
+                parse_and_push_value(binary, addr_part, 2, line_num)?;
             }
         } else if index_part == "Y" {
-            match addr_value {
-                Some(_) => {
-                    binary.push(0x59);
-                    parse_and_push_value(binary, addr_part, 2, line_num)?;
-                },
-                None => {
-                    binary.push(0x59);
-                    if let Some(&address) = labels.get(addr_part) {
-                        binary.push((address & 0xFF) as u8);
-                        binary.push((address >> 8) as u8);
-                    } else {
-                        unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                        binary.push(0);
-                        binary.push(0);
-                    }
-                }
-            }
+            binary.push(0x59);
+            parse_and_push_value(binary, addr_part, 2, line_num)?;
         } else {
             return Err(format!("Line {}: Invalid index register: {}", line_num, index_part));
         }
-    } else if operand.starts_with('$') {
+    } else {
         let value = parse_value(operand, line_num)?;
-        if value <= 0xFF {
+        if is_zero_page(operand) {
             binary.push(0x45);
             binary.push(value as u8);
         } else {
@@ -1428,26 +1849,13 @@ fn compile_eor(
             binary.push((value & 0xFF) as u8);
             binary.push((value >> 8) as u8);
         }
-    } else {
-        binary.push(0x4D);
-        if let Some(&address) = labels.get(operand) {
-            binary.push((address & 0xFF) as u8);
-            binary.push((address >> 8) as u8);
-        } else {
-            unresolved_jumps.push((binary.len(), operand.to_string(), 2));
-            binary.push(0);
-            binary.push(0);
-        }
     }
     Ok(())
 }
 
 fn compile_inc(
     binary: &mut Vec<u8>,
-    unresolved_jumps: &mut Vec<(usize, String, usize)>,
     operand: &str,
-    _current_address: u16,
-    labels: &HashMap<String, u16>,
     line_num: usize
 ) -> Result<(), String> {
     if operand.starts_with('#') {
@@ -1462,35 +1870,17 @@ fn compile_inc(
         if index_part != "X" {
             return Err(format!("Line {}: INC only supports X-indexed addressing", line_num));
         }
-        let addr_value = if addr_part.starts_with('$') {
-            Some(parse_value(addr_part, line_num)?)
+        let addr_value = parse_value(addr_part, line_num)?;
+        if is_zero_page(addr_part) {
+            binary.push(0xF6);
+            binary.push(addr_value as u8);
         } else {
-            None
-        };
-        match addr_value {
-            Some(addr) if addr <= 0xFF => {
-                binary.push(0xF6);
-                binary.push(addr as u8);
-            },
-            Some(_) => {
-                binary.push(0xFE);
-                parse_and_push_value(binary, addr_part, 2, line_num)?;
-            },
-            None => {
-                binary.push(0xFE);
-                if let Some(&address) = labels.get(addr_part) {
-                    binary.push((address & 0xFF) as u8);
-                    binary.push((address >> 8) as u8);
-                } else {
-                    unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                    binary.push(0);
-                    binary.push(0);
-                }
-            }
+            binary.push(0xFE);
+            parse_and_push_value(binary, addr_part, 2, line_num)?;
         }
-    } else if operand.starts_with('$') {
+    } else {
         let value = parse_value(operand, line_num)?;
-        if value <= 0xFF {
+        if is_zero_page(operand) {
             binary.push(0xE6);
             binary.push(value as u8);
         } else {
@@ -1498,26 +1888,13 @@ fn compile_inc(
             binary.push((value & 0xFF) as u8);
             binary.push((value >> 8) as u8);
         }
-    } else {
-        binary.push(0xEE);
-        if let Some(&address) = labels.get(operand) {
-            binary.push((address & 0xFF) as u8);
-            binary.push((address >> 8) as u8);
-        } else {
-            unresolved_jumps.push((binary.len(), operand.to_string(), 2));
-            binary.push(0);
-            binary.push(0);
-        }
     }
     Ok(())
 }
 
 fn compile_dec(
     binary: &mut Vec<u8>,
-    unresolved_jumps: &mut Vec<(usize, String, usize)>,
     operand: &str,
-    _current_address: u16,
-    labels: &HashMap<String, u16>,
     line_num: usize
 ) -> Result<(), String> {
     if operand.starts_with('#') {
@@ -1532,35 +1909,17 @@ fn compile_dec(
         if index_part != "X" {
             return Err(format!("Line {}: DEC only supports X-indexed addressing", line_num));
         }
-        let addr_value = if addr_part.starts_with('$') {
-            Some(parse_value(addr_part, line_num)?)
+        let addr_value = parse_value(addr_part, line_num)?;
+        if is_zero_page(addr_part) {
+            binary.push(0xD6);
+            binary.push(addr_value as u8);
         } else {
-            None
-        };
-        match addr_value {
-            Some(addr) if addr <= 0xFF => {
-                binary.push(0xD6);
-                binary.push(addr as u8);
-            },
-            Some(_) => {
-                binary.push(0xDE);
-                parse_and_push_value(binary, addr_part, 2, line_num)?;
-            },
-            None => {
-                binary.push(0xDE);
-                if let Some(&address) = labels.get(addr_part) {
-                    binary.push((address & 0xFF) as u8);
-                    binary.push((address >> 8) as u8);
-                } else {
-                    unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                    binary.push(0);
-                    binary.push(0);
-                }
-            }
+            // Absolute,X would be 0xDE, which Helios uses for DBG.
+            return Err(format!("Line {}: DEC does not support absolute,X addressing", line_num));
         }
-    } else if operand.starts_with('$') {
+    } else {
         let value = parse_value(operand, line_num)?;
-        if value <= 0xFF {
+        if is_zero_page(operand) {
             binary.push(0xC6);
             binary.push(value as u8);
         } else {
@@ -1568,15 +1927,71 @@ fn compile_dec(
             binary.push((value & 0xFF) as u8);
             binary.push((value >> 8) as u8);
         }
+    }
+    Ok(())
+}
+
+fn compile_bit(
+    binary: &mut Vec<u8>,
+    operand: &str,
+    line_num: usize
+) -> Result<(), String> {
+    if operand.starts_with('#') || operand.contains(',') {
+        return Err(format!("Line {}: BIT only supports zero page and absolute addressing", line_num));
+    } else {
+        let value = parse_value(operand, line_num)?;
+        if is_zero_page(operand) {
+            binary.push(0x24);
+            binary.push(value as u8);
+        } else {
+            binary.push(0x2C);
+            binary.push((value & 0xFF) as u8);
+            binary.push((value >> 8) as u8);
+        }
+    }
+    Ok(())
+}
+
+// Shared by ASL/LSR/ROL/ROR. `opcodes` holds the Accumulator, Zero Page,
+// Zero Page,X, Absolute and Absolute,X encodings in that order.
+fn compile_shift(
+    binary: &mut Vec<u8>,
+    instruction: &str,
+    opcodes: [u8; 5],
+    operand: &str,
+    line_num: usize
+) -> Result<(), String> {
+    if operand.is_empty() || operand.eq_ignore_ascii_case("A") {
+        binary.push(opcodes[0]);
+    } else if operand.starts_with('#') {
+        return Err(format!("Line {}: {} does not support immediate addressing", line_num, instruction));
+    } else if operand.contains(',') {
+        let parts: Vec<&str> = operand.split(',').collect();
+        if parts.len() != 2 {
+            return Err(format!("Line {}: Invalid indexed addressing format: {}", line_num, operand));
+        }
+        let addr_part = parts[0].trim();
+        let index_part = parts[1].trim().to_uppercase();
+        if index_part != "X" {
+            return Err(format!("Line {}: {} only supports X-indexed addressing", line_num, instruction));
+        }
+        let addr_value = parse_value(addr_part, line_num)?;
+        if is_zero_page(addr_part) {
+            binary.push(opcodes[2]);
+            binary.push(addr_value as u8);
+        } else {
+            binary.push(opcodes[4]);
+            parse_and_push_value(binary, addr_part, 2, line_num)?;
+        }
     } else {
-        binary.push(0xCE);
-        if let Some(&address) = labels.get(operand) {
-            binary.push((address & 0xFF) as u8);
-            binary.push((address >> 8) as u8);
+        let value = parse_value(operand, line_num)?;
+        if is_zero_page(operand) {
+            binary.push(opcodes[1]);
+            binary.push(value as u8);
         } else {
-            unresolved_jumps.push((binary.len(), operand.to_string(), 2));
-            binary.push(0);
-            binary.push(0);
+            binary.push(opcodes[3]);
+            binary.push((value & 0xFF) as u8);
+            binary.push((value >> 8) as u8);
         }
     }
     Ok(())
@@ -1584,10 +1999,7 @@ fn compile_dec(
 
 fn compile_cmp(
     binary: &mut Vec<u8>,
-    unresolved_jumps: &mut Vec<(usize, String, usize)>,
     operand: &str,
-    _current_address: u16,
-    labels: &HashMap<String, u16>,
     line_num: usize
 ) -> Result<(), String> {
     if operand.starts_with('#') {
@@ -1608,57 +2020,24 @@ fn compile_cmp(
         }
         let addr_part = parts[0].trim();
         let index_part = parts[1].trim().to_uppercase();
-        let addr_value = if addr_part.starts_with('$') {
-            Some(parse_value(addr_part, line_num)?)
-        } else {
-            None
-        };
+        let addr_value = parse_value(addr_part, line_num)?;
         if index_part == "X" {
-            match addr_value {
-                Some(addr) if addr <= 0xFF => {
-                    binary.push(0xD5);
-                    binary.push(addr as u8);
-                },
-                Some(_) => {
-                    binary.push(0xDD);
-                    parse_and_push_value(binary, addr_part, 2, line_num)?;
-                },
-                None => {
-                    binary.push(0xDD);
-                    if let Some(&address) = labels.get(addr_part) {
-                        binary.push((address & 0xFF) as u8);
-                        binary.push((address >> 8) as u8);
-                    } else {
-                        unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                        binary.push(0);
-                        binary.push(0);
-                    }
-                }
+            if is_zero_page(addr_part) {
+                binary.push(0xD5);
+                binary.push(addr_value as u8);
+            } else {
+                binary.push(0xDD);
+                parse_and_push_value(binary, addr_part, 2, line_num)?;
             }
         } else if index_part == "Y" {
-            match addr_value {
-                Some(_) => {
-                    binary.push(0xD9);
-                    parse_and_push_value(binary, addr_part, 2, line_num)?;
-                },
-                None => {
-                    binary.push(0xD9);
-                    if let Some(&address) = labels.get(addr_part) {
-                        binary.push((address & 0xFF) as u8);
-                        binary.push((address >> 8) as u8);
-                    } else {
-                        unresolved_jumps.push((binary.len(), addr_part.to_string(), 2));
-                        binary.push(0);
-                        binary.push(0);
-                    }
-                }
-            }
+            binary.push(0xD9);
+            parse_and_push_value(binary, addr_part, 2, line_num)?;
         } else {
             return Err(format!("Line {}: Invalid index register: {}", line_num, index_part));
         }
-    } else if operand.starts_with('$') {
+    } else {
         let value = parse_value(operand, line_num)?;
-        if value <= 0xFF {
+        if is_zero_page(operand) {
             binary.push(0xC5);
             binary.push(value as u8);
         } else {
@@ -1666,34 +2045,21 @@ fn compile_cmp(
             binary.push((value & 0xFF) as u8);
             binary.push((value >> 8) as u8);
         }
-    } else {
-        binary.push(0xCD);
-        if let Some(&address) = labels.get(operand) {
-            binary.push((address & 0xFF) as u8);
-            binary.push((address >> 8) as u8);
-        } else {
-            unresolved_jumps.push((binary.len(), operand.to_string(), 2));
-            binary.push(0);
-            binary.push(0);
-        }
     }
     Ok(())
 }
 
 fn compile_cpx(
     binary: &mut Vec<u8>,
-    unresolved_jumps: &mut Vec<(usize, String, usize)>,
     operand: &str,
-    _current_address: u16,
-    labels: &HashMap<String, u16>,
     line_num: usize
 ) -> Result<(), String> {
     if operand.starts_with('#') {
         binary.push(0xE0);
         parse_and_push_value(binary, &operand[1..], 1, line_num)?;
-    } else if operand.starts_with('$') {
+    } else {
         let value = parse_value(operand, line_num)?;
-        if value <= 0xFF {
+        if is_zero_page(operand) {
             binary.push(0xE4);
             binary.push(value as u8);
         } else {
@@ -1701,34 +2067,21 @@ fn compile_cpx(
             binary.push((value & 0xFF) as u8);
             binary.push((value >> 8) as u8);
         }
-    } else {
-        binary.push(0xEC);
-        if let Some(&address) = labels.get(operand) {
-            binary.push((address & 0xFF) as u8);
-            binary.push((address >> 8) as u8);
-        } else {
-            unresolved_jumps.push((binary.len(), operand.to_string(), 2));
-            binary.push(0);
-            binary.push(0);
-        }
     }
     Ok(())
 }
 
 fn compile_cpy(
     binary: &mut Vec<u8>,
-    unresolved_jumps: &mut Vec<(usize, String, usize)>,
     operand: &str,
-    _current_address: u16,
-    labels: &HashMap<String, u16>,
     line_num: usize
 ) -> Result<(), String> {
     if operand.starts_with('#') {
         binary.push(0xC0);
         parse_and_push_value(binary, &operand[1..], 1, line_num)?;
-    } else if operand.starts_with('$') {
+    } else {
         let value = parse_value(operand, line_num)?;
-        if value <= 0xFF {
+        if is_zero_page(operand) {
             binary.push(0xC4);
             binary.push(value as u8);
         } else {
@@ -1736,37 +2089,171 @@ fn compile_cpy(
             binary.push((value & 0xFF) as u8);
             binary.push((value >> 8) as u8);
         }
-    } else {
-        binary.push(0xCC);
-        if let Some(&address) = labels.get(operand) {
-            binary.push((address & 0xFF) as u8);
-            binary.push((address >> 8) as u8);
-        } else {
-            unresolved_jumps.push((binary.len(), operand.to_string(), 2));
-            binary.push(0);
-            binary.push(0);
-        }
     }
     Ok(())
 }
 fn compile_dbg(
     binary: &mut Vec<u8>,
-    unresolved_jumps: &mut Vec<(usize, String, usize)>,
     operand: &str,
-    current_address: u16,
-    labels: &HashMap<String, u16>,
     line_num: usize
 ) -> Result<(), String> {
-    println!("Compiling DBG Instruction");
     let value = parse_value(operand, line_num)?;
-    if value <= 0xFF {
-        // Zero Page
-        binary.push(value as u8);
-    } else {
-        // Absolute
-        binary.push(0xAC);
-        binary.push((value & 0xFF) as u8);
-        binary.push((value >> 8) as u8);
+    if value > 0xFF {
+        return Err(format!("Line {}: DBG only supports zero page addresses", line_num));
+    }
+    binary.push(0xDE);
+    binary.push(value as u8);
+    Ok(())
+}
+
+fn compile_block(
+    binary: &mut Vec<u8>,
+    opcode: u8,
+    instruction: &str,
+    operand: &str,
+    line_num: usize
+) -> Result<(), String> {
+    // The operand is the zero page address of the parameter block
+    let value = parse_value(operand, line_num)?;
+    if value > 0xFF {
+        return Err(format!("Line {}: {} only supports zero page addresses", line_num, instruction));
     }
+    binary.push(opcode);
+    binary.push(value as u8);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Where the .include test fixtures live
+    fn include_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("roms").join("include")
+    }
+
+    #[test]
+    fn org_pads_with_zeros() {
+        assert_eq!(compile("NOP\n.org $0004\nNOP").unwrap(), [0xEA, 0x00, 0x00, 0x00, 0xEA]);
+    }
+
+    #[test]
+    fn org_backwards_is_an_error() {
+        assert_eq!(
+            compile("NOP\nNOP\n.org $0001\nNOP"),
+            Err("Line 3: .org $0001 is behind code that was already placed (up to $0002)".to_string())
+        );
+    }
+
+    #[test]
+    fn byte_and_word() {
+        assert_eq!(compile(".byte 1, $FF, 'A', -1").unwrap(), [0x01, 0xFF, 0x41, 0xFF]);
+        assert_eq!(compile(".word $1234, 1, -1").unwrap(), [0x34, 0x12, 0x01, 0x00, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn fill() {
+        assert_eq!(compile(".fill 3, $AA\n.fill 2").unwrap(), [0xAA, 0xAA, 0xAA, 0x00, 0x00]);
+        assert_eq!(compile(".fill 2, 256"), Err("Line 1: Value 256 does not fit in a byte".to_string()));
+    }
+
+    #[test]
+    fn align() {
+        assert_eq!(compile("NOP\n.align 4, $FF\nNOP").unwrap(), [0xEA, 0xFF, 0xFF, 0xFF, 0xEA]);
+        assert_eq!(compile("NOP\nNOP\nNOP\nNOP\n.align 4\nNOP").unwrap(), [0xEA, 0xEA, 0xEA, 0xEA, 0xEA]);
+        assert_eq!(compile(".align 0"), Err("Line 1: .align boundary must be at least 1".to_string()));
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(compile(".text \"Hi;\\n\"").unwrap(), [0x48, 0x69, 0x3B, 0x0A]);
+    }
+
+    #[test]
+    fn forward_constants() {
+        // SIZE needs labels further down, and LAST needs SIZE
+        let source = "LDA #LAST\nLAST = SIZE - 1\nSIZE = end - start\nstart:\n.byte 1, 2, 3\nend:";
+        assert_eq!(compile(source).unwrap(), [0xA9, 0x02, 0x01, 0x02, 0x03]);
+        assert_eq!(
+            compile("A = B + 1\nB = A\nNOP"),
+            Err("Line 1: Can't work out A: B is undefined or circular".to_string())
+        );
+    }
+
+    #[test]
+    fn negative_immediates() {
+        assert_eq!(compile("NEG = -2\nLDA #-1\nLDA #NEG\nCMP #-128").unwrap(), [0xA9, 0xFF, 0xA9, 0xFE, 0xC9, 0x80]);
+        assert_eq!(compile("LDA #-129"), Err("Line 1: Value -129 does not fit in a byte".to_string()));
+    }
+
+    #[test]
+    fn macro_arguments() {
+        let source = ".macro ld addr\n LDA addr\n.endm\n.macro m c\n LDA #c\n.endm\n ld ($10),Y\n ld $10,X\n m ','";
+        assert_eq!(compile(source).unwrap(), [0xB1, 0x10, 0xB5, 0x10, 0xA9, 0x2C]);
+        assert_eq!(
+            compile(".macro m a\n LDA #a\n.endm\n m 1, 2"),
+            Err("Line 4: Macro m takes 1 arguments but got 2".to_string())
+        );
+    }
+
+    #[test]
+    fn macro_recursion_limit() {
+        let error = compile(".macro r\n r\n.endm\n r").unwrap_err();
+        let chain = vec!["r"; MAX_MACRO_DEPTH].join(" -> ");
+        assert_eq!(error, format!("Line 4: Macros nested more than 32 deep, does r call itself? (in macro {})", chain));
+    }
+
+    #[test]
+    fn macro_labels_are_renamed() {
+        // The label in the macro gets a name no source label can have, so it
+        // doesn't clash with loop__1 or loop
+        let source = "loop__1:\nloop:\n.macro m\nloop:\n JMP loop\n.endm\n m\n JMP loop__1";
+        assert_eq!(compile(source).unwrap(), [0x4C, 0x00, 0x00, 0x4C, 0x00, 0x00]);
+        assert_eq!(compile("loop.1:\n NOP"), Err("Line 1: Invalid label name: loop.1".to_string()));
+        assert_eq!(compile("N.1 = 3"), Err("Line 1: Invalid constant name: N.1".to_string()));
+    }
+
+    #[test]
+    fn anonymous_labels_are_renamed() {
+        let source = "__anonymous_0:\n-\n JMP __anonymous_0\n BNE -";
+        assert_eq!(compile(source).unwrap(), [0x4C, 0x00, 0x00, 0xD0, 0xFB]);
+    }
+
+    #[test]
+    fn include_cycle() {
+        let dir = include_dir();
+        assert_eq!(
+            compile_file(&dir.join("cycle-a.asm")),
+            Err(format!(
+                "Line 3: {} includes itself (in {}, included from {} line 2)",
+                dir.join("cycle-a.asm").display(), dir.join("cycle-b.asm").display(), dir.join("cycle-a.asm").display()
+            ))
+        );
+    }
+
+    #[test]
+    fn include_missing_file() {
+        let dir = include_dir();
+        let error = compile_file(&dir.join("missing.asm")).unwrap_err();
+        let expected = format!("Line 2: Can't read {}: ", dir.join("no-such-file.asm").display());
+        assert!(error.starts_with(&expected), "{}", error);
+    }
+
+    #[test]
+    fn include_chain_in_errors() {
+        let dir = include_dir();
+        assert_eq!(
+            compile_file(&dir.join("error-outer.asm")),
+            Err(format!(
+                "Line 3: Missing value (in {}, included from {} line 2, included from {} line 3)",
+                dir.join("error-inner.asm").display(), dir.join("error-middle.asm").display(), dir.join("error-outer.asm").display()
+            ))
+        );
+    }
+
+    #[test]
+    fn labels_after_directives() {
+        // Labels after data have to land where the second pass puts them
+        assert_eq!(compile(".byte 1, 2\nend:\n.word end").unwrap(), [0x01, 0x02, 0x02, 0x00]);
+    }
+}
@@ -1,6 +1,7 @@
 // src/cpu.rs
-use std::sync::{Arc, Mutex};
-use crate::memory::Memory;
+use std::fmt;
+
+use crate::bus::{Bus, WriteFault};
 use crate::isa;
 
 // CPU Flags
@@ -8,8 +9,71 @@ pub const FLAG_ZERO: u8 = 0b00000001;
 pub const FLAG_NEGATIVE: u8 = 0b00000010;
 pub const FLAG_CARRY: u8 = 0b00000100;
 pub const FLAG_OVERFLOW: u8 = 0b00001000;
+pub const FLAG_INTERRUPT_DISABLE: u8 = 0b00010000;
+pub const FLAG_BREAK: u8 = 0b00100000; // Only ever set on the copy of status pushed by BRK
+pub const FLAG_DECIMAL: u8 = 0b01000000;  // ADC/SBC work on packed BCD digits
+
+// Interrupt vectors (little endian addresses at the top of memory)
+pub const TRAP_VECTOR: u16 = 0xFFF8; // Illegal opcodes, with IllegalOpcodePolicy::Trap
+pub const NMI_VECTOR: u16 = 0xFFFA;
+pub const RESET_VECTOR: u16 = 0xFFFC;
+pub const IRQ_VECTOR: u16 = 0xFFFE; // Shared by IRQ and BRK
+
+// Default clock rate
+pub const DEFAULT_CLOCK_HZ: u64 = 1_000_000; // 1 MHz
+
+// Cycles spent pushing state and fetching the vector when an interrupt is taken
+pub const INTERRUPT_CYCLES: u32 = 7;
+
+// What the CPU does when it fetches an opcode Helios doesn't implement
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum IllegalOpcodePolicy {
+    #[default]
+    Halt, // Stop the CPU
+    Nop,  // Carry on with the next byte (the event still lets the host warn)
+    Trap, // Interrupt through TRAP_VECTOR; the pushed PC is one past the bad opcode
+}
 
-pub struct CPU {
+// Errors the host can inspect through `CPU::last_error` instead of parsing stdout
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuError {
+    IllegalOpcode { pc: u16, opcode: u8 },
+    RomWrite { pc: u16, address: u16, value: u8 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::IllegalOpcode { pc, opcode } => {
+                write!(f, "Illegal opcode {:02X} at address {:04X}", opcode, pc)
+            },
+            CpuError::RomWrite { pc, address, value } => {
+                write!(f, "Write of {:02X} to ROM at {:04X} by the instruction at {:04X}", value, address, pc)
+            },
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+// Things that happened during a step that the host may want to react to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuEvent {
+    Halted,                            // HLT was executed
+    Debug { addr: u16, value: u8 },    // DBG read `value` from zero page address `addr`
+    Sound { byte: u8 },                // SND wrote `byte` to the audio buffer
+    IllegalOpcode { pc: u16, op: u8 }, // Handled according to `illegal_opcode_policy`
+    RomWrite { pc: u16, address: u16, value: u8 }, // Refused by ROM write protection
+}
+
+// The outcome of `CPU::step`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StepResult {
+    pub cycles: u32,
+    pub event: Option<CpuEvent>,
+}
+
+pub struct CPU<B: Bus> {
     // Registers
     pub a: u8,       // Accumulator
     pub x: u8,       // X index register
@@ -18,26 +82,42 @@ pub struct CPU {
     pub sp: u8,      // Stack pointer (0x00-0xFF, stack at 0x0100-0x01FF)
     pub status: u8,  // Status register (flags)
     
-    // Memory
-    pub memory: Arc<Mutex<Memory>>,
+    // Everything the CPU can address (memory and memory-mapped devices)
+    pub bus: B,
     
     // State
     pub cycles: u64,
     pub halted: bool,
+    pub last_error: Option<CpuError>, // Most recent error, kept until reset
+    
+    // Interrupt lines
+    pub irq_line: bool,    // Level triggered, held by the device until acknowledged
+    pub nmi_pending: bool, // Edge triggered, cleared once serviced
+    
+    // Configuration
+    pub use_reset_vector: bool, // Start at the address stored at RESET_VECTOR instead of 0
+    pub illegal_opcode_policy: IllegalOpcodePolicy,
+    pub clock_hz: Option<u64>, // Cycles per second the host should run, None for as fast as possible
 }
 
-impl CPU {
-    pub fn new(memory: Arc<Mutex<Memory>>) -> Self {
+impl<B: Bus> CPU<B> {
+    pub fn new(bus: B) -> Self {
         Self {
             a: 0,
             x: 0,
             y: 0,
             pc: 0,
             sp: 0xFF, // Stack starts at the top and grows downward
-            status: 0,
-            memory,
+            status: FLAG_INTERRUPT_DISABLE, // IRQs stay masked until the program runs CLI
+            bus,
             cycles: 0,
             halted: false,
+            last_error: None,
+            irq_line: false,
+            nmi_pending: false,
+            use_reset_vector: false,
+            illegal_opcode_policy: IllegalOpcodePolicy::Halt,
+            clock_hz: Some(DEFAULT_CLOCK_HZ),
         }
     }
     
@@ -45,48 +125,95 @@ impl CPU {
         self.a = 0;
         self.x = 0;
         self.y = 0;
-        self.pc = 0; // Start execution at address 0
+        // Start execution at address 0 unless the ROM asked for its reset vector
+        self.pc = if self.use_reset_vector { self.read_word(RESET_VECTOR) } else { 0 };
         self.sp = 0xFF;
-        self.status = 0;
+        self.status = FLAG_INTERRUPT_DISABLE;
         self.cycles = 0;
         self.halted = false;
+        self.last_error = None;
+        self.irq_line = false;
+        self.nmi_pending = false;
     }
     
-    pub fn step(&mut self) -> bool {
+    // Runs one instruction (or enters a pending interrupt) and returns the
+    // number of cycles it took along with any event it raised. A halted CPU
+    // does nothing and returns 0 cycles and no event.
+    pub fn step(&mut self) -> StepResult {
         if self.halted {
-            return false;
+            return StepResult { cycles: 0, event: None };
+        }
+        
+        // Service pending interrupts before the next instruction. NMI wins over IRQ.
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.interrupt(NMI_VECTOR, false);
+            self.cycles += INTERRUPT_CYCLES as u64;
+            self.bus.tick(INTERRUPT_CYCLES);
+            return StepResult { cycles: INTERRUPT_CYCLES, event: None };
+        }
+        if (self.irq_line || self.bus.irq()) && !self.get_flag(FLAG_INTERRUPT_DISABLE) {
+            self.interrupt(IRQ_VECTOR, false);
+            self.cycles += INTERRUPT_CYCLES as u64;
+            self.bus.tick(INTERRUPT_CYCLES);
+            return StepResult { cycles: INTERRUPT_CYCLES, event: None };
         }
         
         // Fetch opcode
+        let pc = self.pc;
         let opcode = self.fetch();
         
         // Execute instruction
-        if opcode == 0xDE {
-            println!("Saw the OPCODE: 0xDE");
+        let mut result = isa::execute(self, opcode);
+        
+        // A refused write is reported against the instruction that made it
+        if let Some(fault) = self.bus.take_write_fault() {
+            let WriteFault { address, value, halt } = fault;
+            self.last_error = Some(CpuError::RomWrite { pc, address, value });
+            result.event = Some(CpuEvent::RomWrite { pc, address, value });
+            if halt {
+                self.halt();
+            }
         }
-        isa::execute(self, opcode);
         
-        // Increment cycle count
-        self.cycles += 1;
+        // Increment cycle count and let devices on the bus catch up
+        self.cycles += result.cycles as u64;
+        self.bus.tick(result.cycles);
         
-        !self.halted
+        result
+    }
+    
+    // Runs instructions until at least `budget` cycles have passed or the CPU halts,
+    // appending the events they raise to `events`. Returns the cycles actually
+    // spent, which can overshoot by part of an instruction.
+    pub fn run_cycles(&mut self, budget: u64, events: &mut Vec<CpuEvent>) -> u64 {
+        let mut spent = 0;
+        while spent < budget && !self.halted {
+            let result = self.step();
+            spent += result.cycles as u64;
+            events.extend(result.event);
+        }
+        spent
     }
     
     pub fn fetch(&mut self) -> u8 {
-        let memory = self.memory.lock().unwrap();
-        let opcode = memory.read(self.pc);
+        let opcode = self.bus.read(self.pc);
         self.pc = self.pc.wrapping_add(1);
         opcode
     }
     
-    pub fn read(&self, address: u16) -> u8 {
-        let memory = self.memory.lock().unwrap();
-        memory.read(address)
+    pub fn read(&mut self, address: u16) -> u8 {
+        self.bus.read(address)
     }
     
     pub fn write(&mut self, address: u16, value: u8) {
-        let mut memory = self.memory.lock().unwrap();
-        memory.write(address, value);
+        self.bus.write(address, value);
+    }
+    
+    pub fn read_word(&mut self, address: u16) -> u16 {
+        let low = self.read(address) as u16;
+        let high = self.read(address.wrapping_add(1)) as u16;
+        (high << 8) | low
     }
     
     pub fn push(&mut self, value: u8) {
@@ -118,7 +245,55 @@ impl CPU {
         self.set_flag(FLAG_NEGATIVE, (value & 0x80) != 0);
     }
     
+    // Set the clock rate in Hz, or None to run unthrottled. The CPU itself doesn't
+    // keep time; whoever drives it (e.g. `Machine::run_frame`) paces it by this.
+    pub fn set_clock_hz(&mut self, clock_hz: Option<u64>) {
+        self.clock_hz = clock_hz;
+    }
+    
     pub fn halt(&mut self) {
         self.halted = true;
     }
+    
+    // Handle an opcode Helios doesn't implement (PC is already past it) according
+    // to `illegal_opcode_policy`, returning the cycles it took. The caller reports
+    // it to the host as `CpuEvent::IllegalOpcode`.
+    pub fn illegal_opcode(&mut self, opcode: u8) -> u32 {
+        self.last_error = Some(CpuError::IllegalOpcode { pc: self.pc.wrapping_sub(1), opcode });
+        
+        match self.illegal_opcode_policy {
+            IllegalOpcodePolicy::Halt => {
+                self.halt();
+                2
+            },
+            IllegalOpcodePolicy::Nop => 2,
+            IllegalOpcodePolicy::Trap => {
+                self.interrupt(TRAP_VECTOR, false);
+                INTERRUPT_CYCLES
+            },
+        }
+    }
+    
+    // Raise or release the IRQ line. Devices hold it until the handler acknowledges them.
+    pub fn set_irq(&mut self, active: bool) {
+        self.irq_line = active;
+    }
+    
+    // Request a non-maskable interrupt, serviced before the next instruction
+    pub fn trigger_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+    
+    // Push PC and status, mask further IRQs and jump through `vector`.
+    // `brk` marks the pushed status so a shared handler can tell BRK from IRQ.
+    pub fn interrupt(&mut self, vector: u16, brk: bool) {
+        self.push((self.pc >> 8) as u8);
+        self.push(self.pc as u8);
+        
+        let status = if brk { self.status | FLAG_BREAK } else { self.status & !FLAG_BREAK };
+        self.push(status);
+        
+        self.set_flag(FLAG_INTERRUPT_DISABLE, true);
+        self.pc = self.read_word(vector);
+    }
 }
@@ -1,5 +1,4 @@
 // src/display.rs
-use std::sync::{Arc, Mutex};
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use sdl2::render::Canvas;
@@ -8,7 +7,7 @@ use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use std::time::Duration;
 
-use crate::memory::{Memory, DISPLAY_START, DISPLAY_SIZE};
+use helios::memory::{Memory, DISPLAY_SIZE};
 
 // Constants
 const DISPLAY_WIDTH: usize = 256;
@@ -29,13 +28,13 @@ const COLORS: [Color; 8] = [
 
 pub struct Display {
     canvas: Canvas<Window>,
-    memory: Arc<Mutex<Memory>>,
     event_pump: sdl2::EventPump,
     exit_requested: bool,
+    turbo_toggled: bool, // Tab was pressed since the last `take_turbo_toggle`
 }
 
 impl Display {
-    pub fn new(memory: Arc<Mutex<Memory>>) -> Self {
+    pub fn new() -> Self {
         let sdl_context = sdl2::init().unwrap();
         let video_subsystem = sdl_context.video().unwrap();
         
@@ -53,13 +52,14 @@ impl Display {
         
         Self {
             canvas,
-            memory,
             event_pump,
             exit_requested: false,
+            turbo_toggled: false,
         }
     }
     
-    pub fn update(&mut self) {
+    // Handle window events and draw the current contents of display memory
+    pub fn update(&mut self, memory: &Memory) {
         // Handle SDL events
         for event in self.event_pump.poll_iter() {
             match event {
@@ -67,6 +67,9 @@ impl Display {
                 Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
                     self.exit_requested = true;
                 },
+                Event::KeyDown { keycode: Some(Keycode::Tab), repeat: false, .. } => {
+                    self.turbo_toggled = !self.turbo_toggled;
+                },
                 _ => {}
             }
         }
@@ -76,7 +79,6 @@ impl Display {
         self.canvas.clear();
         
         // Draw the display buffer
-        let memory = self.memory.lock().unwrap();
         let display_buffer = memory.get_display_buffer();
         
         for y in 0..DISPLAY_HEIGHT {
@@ -104,4 +106,9 @@ impl Display {
     pub fn should_exit(&self) -> bool {
         self.exit_requested
     }
+    
+    // Whether turbo mode should be flipped, i.e. Tab was pressed an odd number of times
+    pub fn take_turbo_toggle(&mut self) -> bool {
+        std::mem::take(&mut self.turbo_toggled)
+    }
 }
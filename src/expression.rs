@@ -0,0 +1,373 @@
+// src/expression.rs
+// Operand expressions for the assembler: numbers, symbols, `*` for the current
+// address and the operators + - * / & | ^ << >>, parentheses and unary -, < (low
+// byte) and > (high byte). Precedence follows C, with the unary operators binding tightest.
+
+// An evaluated expression
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Value {
+    pub value: u16, // Negative values are stored as 16 bit two's complement
+    // Whether the value may be assembled as a zero page address. Labels, `*` and
+    // hex literals written with more than two digits always mean a full address.
+    pub zero_page: bool,
+    pub negative: bool,
+}
+
+impl Value {
+    // The value as a single byte: 0 to 255, or -128 to -1 as two's complement
+    pub fn byte(&self) -> Option<u8> {
+        if self.value <= 0xFF || (self.negative && self.value >= 0xFF80) {
+            Some(self.value as u8)
+        } else {
+            None
+        }
+    }
+}
+
+pub enum Evaluation {
+    Known(Value),
+    Undefined(String), // The first symbol that isn't defined (yet)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(i64, bool), // Value, and whether it may be zero page
+    Symbol(String),
+    Op(&'static str),
+}
+
+// Intermediate result: None once an undefined symbol has been seen
+type Term = Option<(i64, bool)>;
+
+// Evaluates `expr`, looking symbols up with `lookup`. `pc` is the value of `*`.
+pub fn evaluate(
+    expr: &str,
+    line_num: usize,
+    pc: u16,
+    lookup: &dyn Fn(&str) -> Option<Value>
+) -> Result<Evaluation, String> {
+    let tokens = tokenize(expr, line_num)?;
+    let mut parser = Parser { expr, tokens, position: 0, line_num, pc, lookup, undefined: None };
+
+    let result = parser.binary(0)?;
+    if parser.position < parser.tokens.len() {
+        return Err(format!("Line {}: Unexpected {:?} in expression: {}", line_num, parser.tokens[parser.position], expr));
+    }
+
+    let (value, zero_page) = match result {
+        Some(result) => result,
+        None => return Ok(Evaluation::Undefined(parser.undefined.unwrap_or_default())),
+    };
+    if !(-0x8000..=0xFFFF).contains(&value) {
+        return Err(format!("Line {}: Value of {} is out of range: {}", line_num, expr, value));
+    }
+    Ok(Evaluation::Known(Value {
+        value: value as u16,
+        zero_page: zero_page && (0..=0xFF).contains(&value),
+        negative: value < 0,
+    }))
+}
+
+// Whether `name` can be used as a label or constant name
+pub fn is_symbol_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {},
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn tokenize(expr: &str, line_num: usize) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = expr.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        // Reads a run of characters matching `accept` starting at `from`
+        let run = |from: usize, accept: &dyn Fn(char) -> bool| -> String {
+            chars[from..].iter().take_while(|&&c| accept(c)).collect()
+        };
+
+        match c {
+            '$' => {
+                let digits = run(i + 1, &|c| c.is_ascii_hexdigit());
+                let value = i64::from_str_radix(&digits, 16)
+                    .map_err(|_| format!("Line {}: Invalid hexadecimal value in: {}", line_num, expr))?;
+                tokens.push(Token::Number(value, digits.len() <= 2));
+                i += 1 + digits.len();
+            },
+            '%' => {
+                let digits = run(i + 1, &|c| c == '0' || c == '1');
+                let value = i64::from_str_radix(&digits, 2)
+                    .map_err(|_| format!("Line {}: Invalid binary value in: {}", line_num, expr))?;
+                tokens.push(Token::Number(value, digits.len() <= 8));
+                i += 1 + digits.len();
+            },
+            '0'..='9' => {
+                let digits = run(i, &|c| c.is_ascii_digit());
+                let value = digits.parse::<i64>()
+                    .map_err(|_| format!("Line {}: Invalid decimal value in: {}", line_num, expr))?;
+                tokens.push(Token::Number(value, value <= 0xFF));
+                i += digits.len();
+            },
+            '\'' => {
+                // Character literal, e.g. 'A'
+                if i + 2 >= chars.len() || chars[i + 2] != '\'' || !chars[i + 1].is_ascii() {
+                    return Err(format!("Line {}: Invalid character literal in: {}", line_num, expr));
+                }
+                tokens.push(Token::Number(chars[i + 1] as i64, true));
+                i += 3;
+            },
+            c if c.is_ascii_alphabetic() || c == '_' || c == '.' => {
+                let name = run(i, &|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+                i += name.len();
+                tokens.push(Token::Symbol(name));
+            },
+            _ => {
+                let rest: String = chars[i..].iter().collect();
+                let op = ["<<", ">>", "+", "-", "*", "/", "&", "|", "^", "<", ">", "(", ")"]
+                    .iter()
+                    .find(|op| rest.starts_with(**op))
+                    .ok_or_else(|| format!("Line {}: Unexpected character '{}' in expression: {}", line_num, c, expr))?;
+                tokens.push(Token::Op(op));
+                i += op.len();
+            },
+        }
+    }
+
+    if tokens.is_empty() {
+        return Err(format!("Line {}: Missing value", line_num));
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    expr: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+    line_num: usize,
+    pc: u16,
+    lookup: &'a dyn Fn(&str) -> Option<Value>,
+    undefined: Option<String>,
+}
+
+// Binary operators from lowest to highest precedence
+const PRECEDENCE: [&[&str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/"]];
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    // Parses operators of precedence `level` and above
+    fn binary(&mut self, level: usize) -> Result<Term, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(level + 1)?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            if !PRECEDENCE[level].contains(&op) {
+                break;
+            }
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+
+            left = match (left, right) {
+                (Some((a, a_zp)), Some((b, b_zp))) => {
+                    let value = match op {
+                        "|" => Some(a | b),
+                        "^" => Some(a ^ b),
+                        "&" => Some(a & b),
+                        "<<" => Some(u32::try_from(b).ok().and_then(|b| a.checked_shl(b)).unwrap_or(0)),
+                        ">>" => Some(u32::try_from(b).ok().and_then(|b| a.checked_shr(b)).unwrap_or(0)),
+                        "+" => a.checked_add(b),
+                        "-" => a.checked_sub(b),
+                        "*" => a.checked_mul(b),
+                        "/" => {
+                            if b == 0 {
+                                return Err(format!("Line {}: Division by zero in expression", self.line_num));
+                            }
+                            a.checked_div(b)
+                        },
+                        _ => unreachable!(),
+                    };
+                    Some((value.ok_or_else(|| self.out_of_range())?, a_zp && b_zp))
+                },
+                _ => None,
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Term, String> {
+        match self.peek() {
+            Some(Token::Op("-")) => {
+                self.position += 1;
+                match self.unary()? {
+                    Some((value, zero_page)) => Ok(Some((value.checked_neg().ok_or_else(|| self.out_of_range())?, zero_page))),
+                    None => Ok(None),
+                }
+            },
+            Some(Token::Op("<")) => {
+                // Low byte
+                self.position += 1;
+                Ok(self.unary()?.map(|(value, _)| (value & 0xFF, true)))
+            },
+            Some(Token::Op(">")) => {
+                // High byte
+                self.position += 1;
+                Ok(self.unary()?.map(|(value, _)| ((value >> 8) & 0xFF, true)))
+            },
+            _ => self.primary(),
+        }
+    }
+
+    // Intermediate results are i64, far beyond anything that fits the final 16 bits
+    fn out_of_range(&self) -> String {
+        format!("Line {}: Value of {} is out of range", self.line_num, self.expr)
+    }
+
+    fn primary(&mut self) -> Result<Term, String> {
+        match self.next() {
+            Some(Token::Number(value, zero_page)) => Ok(Some((value, zero_page))),
+            Some(Token::Symbol(name)) => match (self.lookup)(&name) {
+                Some(symbol) => {
+                    let value = if symbol.negative { symbol.value as i16 as i64 } else { symbol.value as i64 };
+                    Ok(Some((value, symbol.zero_page)))
+                },
+                None => {
+                    self.undefined.get_or_insert(name);
+                    Ok(None)
+                },
+            },
+            Some(Token::Op("*")) => Ok(Some((self.pc as i64, false))), // Current address
+            Some(Token::Op("(")) => {
+                let value = self.binary(0)?;
+                match self.next() {
+                    Some(Token::Op(")")) => Ok(value),
+                    _ => Err(format!("Line {}: Missing ')' in expression", self.line_num)),
+                }
+            },
+            Some(token) => Err(format!("Line {}: Unexpected {:?} in expression", self.line_num, token)),
+            None => Err(format!("Line {}: Expression ends too early", self.line_num)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<Value> {
+        match name {
+            "five" => Some(Value { value: 5, zero_page: true, negative: false }),
+            "label" => Some(Value { value: 0x1234, zero_page: false, negative: false }),
+            "minus_two" => Some(Value { value: 0xFFFE, zero_page: false, negative: true }),
+            _ => None,
+        }
+    }
+
+    // Evaluates `expr` with `*` at $0300
+    fn eval(expr: &str) -> Result<Value, String> {
+        match evaluate(expr, 1, 0x0300, &lookup)? {
+            Evaluation::Known(value) => Ok(value),
+            Evaluation::Undefined(name) => Err(format!("Undefined: {}", name)),
+        }
+    }
+
+    fn value(expr: &str) -> u16 {
+        eval(expr).unwrap().value
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(value("2+3*4"), 14);
+        assert_eq!(value("(2+3)*4"), 20);
+        assert_eq!(value("10-4-3"), 3);
+        assert_eq!(value("100/7"), 14);
+        assert_eq!(value("1<<2+1"), 8);
+        assert_eq!(value("1|2&3"), 3);
+        assert_eq!(value("6^3&1"), 7);
+        assert_eq!(value("-2*3+10"), 4);
+    }
+
+    #[test]
+    fn numbers_symbols_and_pc() {
+        assert_eq!(value("$1F + %101 + 10 + 'A'"), 0x1F + 5 + 10 + 65);
+        assert_eq!(value("label+five"), 0x1239);
+        assert_eq!(value("* + 2"), 0x0302);
+    }
+
+    #[test]
+    fn byte_selection() {
+        assert_eq!(value("<label"), 0x34);
+        assert_eq!(value(">label"), 0x12);
+        assert_eq!(value("<label+1"), 0x35); // < binds tighter than +
+        assert_eq!(value("<(label+$100)"), 0x34);
+        assert_eq!(value(">(label+$100)"), 0x13);
+        assert!(eval("<label").unwrap().zero_page);
+    }
+
+    #[test]
+    fn zero_page() {
+        assert!(eval("$10").unwrap().zero_page);
+        assert!(eval("five+1").unwrap().zero_page);
+        assert!(!eval("$0010").unwrap().zero_page);
+        assert!(!eval("label").unwrap().zero_page);
+        assert!(!eval("*").unwrap().zero_page);
+        assert!(!eval("$FF+1").unwrap().zero_page);
+    }
+
+    #[test]
+    fn negative_bytes() {
+        assert_eq!(eval("-1").unwrap().value, 0xFFFF);
+        assert_eq!(eval("-1").unwrap().byte(), Some(0xFF));
+        assert_eq!(eval("-128").unwrap().byte(), Some(0x80));
+        assert_eq!(eval("minus_two+1").unwrap().byte(), Some(0xFF));
+        assert_eq!(eval("255").unwrap().byte(), Some(0xFF));
+        assert_eq!(eval("-129").unwrap().byte(), None);
+        assert_eq!(eval("256").unwrap().byte(), None);
+        assert_eq!(eval("$FFFF").unwrap().byte(), None); // Not negative, just large
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert_eq!(eval("$10000"), Err("Line 1: Value of $10000 is out of range: 65536".to_string()));
+        assert_eq!(eval("-$8001"), Err("Line 1: Value of -$8001 is out of range: -32769".to_string()));
+        // Intermediate results that don't fit an i64 are errors, not panics
+        let error = Err("Line 1: Value of $7FFFFFFFFFFFFFFF+1 is out of range".to_string());
+        assert_eq!(eval("$7FFFFFFFFFFFFFFF+1"), error);
+        assert!(eval("2*$4000000000000000").is_err());
+        assert!(eval("-$7FFFFFFFFFFFFFFF-2").is_err());
+        assert_eq!(value("1<<64"), 0);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(eval("1/0"), Err("Line 1: Division by zero in expression".to_string()));
+        assert_eq!(eval("(1+2"), Err("Line 1: Missing ')' in expression".to_string()));
+        assert_eq!(eval("1+"), Err("Line 1: Expression ends too early".to_string()));
+        assert_eq!(eval("1 # 2"), Err("Line 1: Unexpected character '#' in expression: 1 # 2".to_string()));
+    }
+
+    #[test]
+    fn undefined_symbols() {
+        assert_eq!(eval("later+1"), Err("Undefined: later".to_string()));
+        assert_eq!(eval("five+later*other"), Err("Undefined: later".to_string()));
+    }
+}
@@ -1,55 +1,204 @@
 // src/isa.rs
-use crate::cpu::CPU;
-use crate::cpu::{FLAG_CARRY, FLAG_ZERO, FLAG_NEGATIVE, FLAG_OVERFLOW};
+use crate::bus::Bus;
+use crate::cpu::{CpuEvent, StepResult, CPU};
+use crate::cpu::{FLAG_CARRY, FLAG_ZERO, FLAG_NEGATIVE, FLAG_OVERFLOW, FLAG_INTERRUPT_DISABLE, FLAG_BREAK, FLAG_DECIMAL};
+use crate::cpu::IRQ_VECTOR;
 
 // OpCodes
 const OP_LDA_IMM: u8 = 0xA9; // Load Accumulator (Immediate)
 const OP_LDA_ZP: u8 = 0xA5;  // Load Accumulator (Zero Page)
 const OP_LDA_ZPX: u8 = 0xB5; // Load Accumulator (Zero Page,X)
 const OP_LDA_ABS: u8 = 0xAD; // Load Accumulator (Absolute)
+const OP_LDA_ABSX: u8 = 0xBD; // Load Accumulator (Absolute,X)
+const OP_LDA_ABSY: u8 = 0xB9; // Load Accumulator (Absolute,Y)
+const OP_LDA_INDX: u8 = 0xA1; // Load Accumulator ((Zero Page,X))
+const OP_LDA_INDY: u8 = 0xB1; // Load Accumulator ((Zero Page),Y)
 const OP_LDX_IMM: u8 = 0xA2; // Load X Register (Immediate)
+const OP_LDX_ZP: u8 = 0xA6;  // Load X Register (Zero Page)
+const OP_LDX_ZPY: u8 = 0xB6; // Load X Register (Zero Page,Y)
+const OP_LDX_ABS: u8 = 0xAE; // Load X Register (Absolute)
+const OP_LDX_ABSY: u8 = 0xBE; // Load X Register (Absolute,Y)
 const OP_LDY_IMM: u8 = 0xA0; // Load Y Register (Immediate)
+const OP_LDY_ZP: u8 = 0xA4;  // Load Y Register (Zero Page)
+const OP_LDY_ZPX: u8 = 0xB4; // Load Y Register (Zero Page,X)
+const OP_LDY_ABS: u8 = 0xAC; // Load Y Register (Absolute)
+const OP_LDY_ABSX: u8 = 0xBC; // Load Y Register (Absolute,X)
 const OP_STA_ZP: u8 = 0x85;  // Store Accumulator (Zero Page)
 const OP_STA_ZPX: u8 = 0x95; // Store Accumulator (Zero Page,X)
 const OP_STA_ABS: u8 = 0x8D; // Store Accumulator (Absolute)
+const OP_STA_ABSX: u8 = 0x9D; // Store Accumulator (Absolute,X)
+const OP_STA_ABSY: u8 = 0x99; // Store Accumulator (Absolute,Y)
+const OP_STA_INDX: u8 = 0x81; // Store Accumulator ((Zero Page,X))
+const OP_STA_INDY: u8 = 0x91; // Store Accumulator ((Zero Page),Y)
 const OP_STX_ZP: u8 = 0x86;  // Store X Register (Zero Page)
+const OP_STX_ZPY: u8 = 0x96; // Store X Register (Zero Page,Y)
+const OP_STX_ABS: u8 = 0x8E; // Store X Register (Absolute)
 const OP_STY_ZP: u8 = 0x84;  // Store Y Register (Zero Page)
+const OP_STY_ZPX: u8 = 0x94; // Store Y Register (Zero Page,X)
+const OP_STY_ABS: u8 = 0x8C; // Store Y Register (Absolute)
 const OP_TAX: u8 = 0xAA;     // Transfer Accumulator to X
 const OP_TAY: u8 = 0xA8;     // Transfer Accumulator to Y
 const OP_TXA: u8 = 0x8A;     // Transfer X to Accumulator
 const OP_TYA: u8 = 0x98;     // Transfer Y to Accumulator
+const OP_TSX: u8 = 0xBA;     // Transfer Stack Pointer to X
+const OP_TXS: u8 = 0x9A;     // Transfer X to Stack Pointer
+const OP_PHA: u8 = 0x48;     // Push Accumulator
+const OP_PLA: u8 = 0x68;     // Pull Accumulator
+const OP_PHP: u8 = 0x08;     // Push Processor Status
+const OP_PLP: u8 = 0x28;     // Pull Processor Status
 const OP_ADC_IMM: u8 = 0x69; // Add with Carry (Immediate)
+const OP_ADC_ZP: u8 = 0x65;  // Add with Carry (Zero Page)
+const OP_ADC_ZPX: u8 = 0x75; // Add with Carry (Zero Page,X)
+const OP_ADC_ABS: u8 = 0x6D; // Add with Carry (Absolute)
+const OP_ADC_ABSX: u8 = 0x7D; // Add with Carry (Absolute,X)
+const OP_ADC_ABSY: u8 = 0x79; // Add with Carry (Absolute,Y)
+const OP_ADC_INDX: u8 = 0x61; // Add with Carry ((Zero Page,X))
+const OP_ADC_INDY: u8 = 0x71; // Add with Carry ((Zero Page),Y)
 const OP_SBC_IMM: u8 = 0xE9; // Subtract with Carry (Immediate)
+const OP_SBC_ZP: u8 = 0xE5;  // Subtract with Carry (Zero Page)
+const OP_SBC_ZPX: u8 = 0xF5; // Subtract with Carry (Zero Page,X)
+const OP_SBC_ABS: u8 = 0xED; // Subtract with Carry (Absolute)
+const OP_SBC_ABSX: u8 = 0xFD; // Subtract with Carry (Absolute,X)
+const OP_SBC_ABSY: u8 = 0xF9; // Subtract with Carry (Absolute,Y)
+const OP_SBC_INDX: u8 = 0xE1; // Subtract with Carry ((Zero Page,X))
+const OP_SBC_INDY: u8 = 0xF1; // Subtract with Carry ((Zero Page),Y)
 const OP_AND_IMM: u8 = 0x29; // Logical AND (Immediate)
+const OP_AND_ZP: u8 = 0x25;  // Logical AND (Zero Page)
+const OP_AND_ZPX: u8 = 0x35; // Logical AND (Zero Page,X)
+const OP_AND_ABS: u8 = 0x2D; // Logical AND (Absolute)
+const OP_AND_ABSX: u8 = 0x3D; // Logical AND (Absolute,X)
+const OP_AND_ABSY: u8 = 0x39; // Logical AND (Absolute,Y)
+const OP_AND_INDX: u8 = 0x21; // Logical AND ((Zero Page,X))
+const OP_AND_INDY: u8 = 0x31; // Logical AND ((Zero Page),Y)
 const OP_ORA_IMM: u8 = 0x09; // Logical OR (Immediate)
+const OP_ORA_ZP: u8 = 0x05;  // Logical OR (Zero Page)
+const OP_ORA_ZPX: u8 = 0x15; // Logical OR (Zero Page,X)
+const OP_ORA_ABS: u8 = 0x0D; // Logical OR (Absolute)
+const OP_ORA_ABSX: u8 = 0x1D; // Logical OR (Absolute,X)
+const OP_ORA_ABSY: u8 = 0x19; // Logical OR (Absolute,Y)
+const OP_ORA_INDX: u8 = 0x01; // Logical OR ((Zero Page,X))
+const OP_ORA_INDY: u8 = 0x11; // Logical OR ((Zero Page),Y)
 const OP_EOR_IMM: u8 = 0x49; // Logical Exclusive OR (Immediate)
+const OP_EOR_ZP: u8 = 0x45;  // Logical Exclusive OR (Zero Page)
+const OP_EOR_ZPX: u8 = 0x55; // Logical Exclusive OR (Zero Page,X)
+const OP_EOR_ABS: u8 = 0x4D; // Logical Exclusive OR (Absolute)
+const OP_EOR_ABSX: u8 = 0x5D; // Logical Exclusive OR (Absolute,X)
+const OP_EOR_ABSY: u8 = 0x59; // Logical Exclusive OR (Absolute,Y)
+const OP_EOR_INDX: u8 = 0x41; // Logical Exclusive OR ((Zero Page,X))
+const OP_EOR_INDY: u8 = 0x51; // Logical Exclusive OR ((Zero Page),Y)
 const OP_INC_ZP: u8 = 0xE6;  // Increment Memory (Zero Page)
+const OP_INC_ZPX: u8 = 0xF6; // Increment Memory (Zero Page,X)
+const OP_INC_ABS: u8 = 0xEE; // Increment Memory (Absolute)
+const OP_INC_ABSX: u8 = 0xFE; // Increment Memory (Absolute,X)
 const OP_DEC_ZP: u8 = 0xC6;  // Decrement Memory (Zero Page)
+const OP_DEC_ZPX: u8 = 0xD6; // Decrement Memory (Zero Page,X)
+const OP_DEC_ABS: u8 = 0xCE; // Decrement Memory (Absolute)
+const OP_ASL_ACC: u8 = 0x0A; // Arithmetic Shift Left (Accumulator)
+const OP_ASL_ZP: u8 = 0x06;  // Arithmetic Shift Left (Zero Page)
+const OP_ASL_ZPX: u8 = 0x16; // Arithmetic Shift Left (Zero Page,X)
+const OP_ASL_ABS: u8 = 0x0E; // Arithmetic Shift Left (Absolute)
+const OP_ASL_ABSX: u8 = 0x1E; // Arithmetic Shift Left (Absolute,X)
+const OP_LSR_ACC: u8 = 0x4A; // Logical Shift Right (Accumulator)
+const OP_LSR_ZP: u8 = 0x46;  // Logical Shift Right (Zero Page)
+const OP_LSR_ZPX: u8 = 0x56; // Logical Shift Right (Zero Page,X)
+const OP_LSR_ABS: u8 = 0x4E; // Logical Shift Right (Absolute)
+const OP_LSR_ABSX: u8 = 0x5E; // Logical Shift Right (Absolute,X)
+const OP_ROL_ACC: u8 = 0x2A; // Rotate Left (Accumulator)
+const OP_ROL_ZP: u8 = 0x26;  // Rotate Left (Zero Page)
+const OP_ROL_ZPX: u8 = 0x36; // Rotate Left (Zero Page,X)
+const OP_ROL_ABS: u8 = 0x2E; // Rotate Left (Absolute)
+const OP_ROL_ABSX: u8 = 0x3E; // Rotate Left (Absolute,X)
+const OP_ROR_ACC: u8 = 0x6A; // Rotate Right (Accumulator)
+const OP_ROR_ZP: u8 = 0x66;  // Rotate Right (Zero Page)
+const OP_ROR_ZPX: u8 = 0x76; // Rotate Right (Zero Page,X)
+const OP_ROR_ABS: u8 = 0x6E; // Rotate Right (Absolute)
+const OP_ROR_ABSX: u8 = 0x7E; // Rotate Right (Absolute,X)
 const OP_INX: u8 = 0xE8;     // Increment X Register
 const OP_INY: u8 = 0xC8;     // Increment Y Register
 const OP_DEX: u8 = 0xCA;     // Decrement X Register
 const OP_DEY: u8 = 0x88;     // Decrement Y Register
 const OP_CMP_IMM: u8 = 0xC9; // Compare Accumulator (Immediate)
+const OP_CMP_ZP: u8 = 0xC5;  // Compare Accumulator (Zero Page)
+const OP_CMP_ZPX: u8 = 0xD5; // Compare Accumulator (Zero Page,X)
+const OP_CMP_ABS: u8 = 0xCD; // Compare Accumulator (Absolute)
+const OP_CMP_ABSX: u8 = 0xDD; // Compare Accumulator (Absolute,X)
+const OP_CMP_ABSY: u8 = 0xD9; // Compare Accumulator (Absolute,Y)
+const OP_CMP_INDX: u8 = 0xC1; // Compare Accumulator ((Zero Page,X))
+const OP_CMP_INDY: u8 = 0xD1; // Compare Accumulator ((Zero Page),Y)
 const OP_CPX_IMM: u8 = 0xE0; // Compare X Register (Immediate)
+const OP_CPX_ZP: u8 = 0xE4;  // Compare X Register (Zero Page)
+const OP_CPX_ABS: u8 = 0xEC; // Compare X Register (Absolute)
 const OP_CPY_IMM: u8 = 0xC0; // Compare Y Register (Immediate)
+const OP_CPY_ZP: u8 = 0xC4;  // Compare Y Register (Zero Page)
+const OP_CPY_ABS: u8 = 0xCC; // Compare Y Register (Absolute)
+const OP_BIT_ZP: u8 = 0x24;  // Bit Test (Zero Page)
+const OP_BIT_ABS: u8 = 0x2C; // Bit Test (Absolute)
 const OP_JMP_ABS: u8 = 0x4C; // Jump (Absolute)
+const OP_JMP_IND: u8 = 0x6C; // Jump (Indirect)
 const OP_JSR_ABS: u8 = 0x20; // Jump to Subroutine
 const OP_RTS: u8 = 0x60;     // Return from Subroutine
+const OP_RTI: u8 = 0x40;     // Return from Interrupt
 const OP_BEQ: u8 = 0xF0;     // Branch if Equal
 const OP_BNE: u8 = 0xD0;     // Branch if Not Equal
 const OP_BCS: u8 = 0xB0;     // Branch if Carry Set
 const OP_BCC: u8 = 0x90;     // Branch if Carry Clear
 const OP_BMI: u8 = 0x30;     // Branch if Minus
 const OP_BPL: u8 = 0x10;     // Branch if Plus
+const OP_BVS: u8 = 0x70;     // Branch if Overflow Set
+const OP_BVC: u8 = 0x50;     // Branch if Overflow Clear
+const OP_CLC: u8 = 0x18;     // Clear Carry Flag
+const OP_SEC: u8 = 0x38;     // Set Carry Flag
+const OP_CLV: u8 = 0xB8;     // Clear Overflow Flag
+const OP_SEI: u8 = 0x78;     // Set Interrupt Disable
+const OP_CLI: u8 = 0x58;     // Clear Interrupt Disable
+const OP_SED: u8 = 0xF8;     // Set Decimal Mode
+const OP_CLD: u8 = 0xD8;     // Clear Decimal Mode
 const OP_NOP: u8 = 0xEA;     // No Operation
 const OP_BRK: u8 = 0x00;     // Break / Force Interrupt
 const OP_HLT: u8 = 0xFF;     // Halt (custom opcode for our emulator)
 const OP_DBG: u8 = 0xDE;     // Debug stack address
 
+// Arithmetic opcodes (custom)
+const OP_MUL: u8 = 0x02;     // Multiply A * Y -> X:A
+const OP_DIV: u8 = 0x22;     // Divide X:A / Y -> A remainder X
+
+// Block opcodes (custom). The operand is a zero page address holding a 6 byte
+// parameter block: source, destination and length, each a little-endian word
+const OP_MOV: u8 = 0x12;     // Copy a block of memory
+const OP_FIL: u8 = 0x32;     // Fill a block of memory with A
+
 // Audio opcodes
 const OP_SND: u8 = 0x42;     // Custom sound opcode
 
-pub fn execute(cpu: &mut CPU, opcode: u8) {
+// Base cycle cost of every opcode. Page-crossing and taken-branch penalties and
+// the per-byte cost of MOV/FIL are added by `execute`. Entries for opcodes
+// Helios doesn't implement are unused, `CPU::illegal_opcode` decides their cost.
+const CYCLES: [u8; 256] = [
+//  x0 x1 x2 x3 x4 x5 x6 x7 x8 x9 xA xB xC xD xE xF
+    7, 6, 8, 2, 2, 3, 5, 2, 3, 2, 2, 2, 2, 4, 6, 2, // 0x
+    2, 5, 7, 2, 2, 4, 6, 2, 2, 4, 2, 2, 2, 4, 7, 2, // 1x
+    6, 6, 12, 2, 3, 3, 5, 2, 4, 2, 2, 2, 4, 4, 6, 2, // 2x
+    2, 5, 7, 2, 2, 4, 6, 2, 2, 4, 2, 2, 2, 4, 7, 2, // 3x
+    6, 6, 3, 2, 2, 3, 5, 2, 3, 2, 2, 2, 3, 4, 6, 2, // 4x
+    2, 5, 2, 2, 2, 4, 6, 2, 2, 4, 2, 2, 2, 4, 7, 2, // 5x
+    6, 6, 2, 2, 2, 3, 5, 2, 4, 2, 2, 2, 5, 4, 6, 2, // 6x
+    2, 5, 2, 2, 2, 4, 6, 2, 2, 4, 2, 2, 2, 4, 7, 2, // 7x
+    2, 6, 2, 2, 3, 3, 3, 2, 2, 2, 2, 2, 4, 4, 4, 2, // 8x
+    2, 6, 2, 2, 4, 4, 4, 2, 2, 5, 2, 2, 2, 5, 2, 2, // 9x
+    2, 6, 2, 2, 3, 3, 3, 2, 2, 2, 2, 2, 4, 4, 4, 2, // Ax
+    2, 5, 2, 2, 4, 4, 4, 2, 2, 4, 2, 2, 4, 4, 4, 2, // Bx
+    2, 6, 2, 2, 3, 3, 5, 2, 2, 2, 2, 2, 4, 4, 6, 2, // Cx
+    2, 5, 2, 2, 2, 4, 6, 2, 2, 4, 2, 2, 2, 4, 3, 2, // Dx
+    2, 6, 2, 2, 3, 3, 5, 2, 2, 2, 2, 2, 4, 4, 6, 2, // Ex
+    2, 5, 2, 2, 2, 4, 6, 2, 2, 4, 2, 2, 2, 4, 7, 1, // Fx
+];
+
+// Executes one instruction and returns the number of cycles it took, plus
+// anything the host should hear about (debug output, sound, halting)
+pub fn execute<B: Bus>(cpu: &mut CPU<B>, opcode: u8) -> StepResult {
+    let mut cycles = CYCLES[opcode as usize] as u32;
+    let mut event = None;
+    
     match opcode {
         OP_LDA_IMM => {
             let value = cpu.fetch();
@@ -74,16 +223,81 @@ pub fn execute(cpu: &mut CPU, opcode: u8) {
             cpu.a = cpu.read(address);
             cpu.update_zero_and_negative_flags(cpu.a);
         },
+        OP_LDA_ABSX => {
+            let (address, page_crossed) = absolute_x(cpu);
+            cycles += page_crossed as u32;
+            cpu.a = cpu.read(address);
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_LDA_ABSY => {
+            let (address, page_crossed) = absolute_y(cpu);
+            cycles += page_crossed as u32;
+            cpu.a = cpu.read(address);
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_LDA_INDX => {
+            let address = indexed_indirect(cpu);
+            cpu.a = cpu.read(address);
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_LDA_INDY => {
+            let (address, page_crossed) = indirect_indexed(cpu);
+            cycles += page_crossed as u32;
+            cpu.a = cpu.read(address);
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
         OP_LDX_IMM => {
             let value = cpu.fetch();
             cpu.x = value;
             cpu.update_zero_and_negative_flags(cpu.x);
         },
+        OP_LDX_ZP => {
+            let address = zero_page(cpu);
+            cpu.x = cpu.read(address);
+            cpu.update_zero_and_negative_flags(cpu.x);
+        },
+        OP_LDX_ZPY => {
+            let address = zero_page_y(cpu);
+            cpu.x = cpu.read(address);
+            cpu.update_zero_and_negative_flags(cpu.x);
+        },
+        OP_LDX_ABS => {
+            let address = absolute(cpu);
+            cpu.x = cpu.read(address);
+            cpu.update_zero_and_negative_flags(cpu.x);
+        },
+        OP_LDX_ABSY => {
+            let (address, page_crossed) = absolute_y(cpu);
+            cycles += page_crossed as u32;
+            cpu.x = cpu.read(address);
+            cpu.update_zero_and_negative_flags(cpu.x);
+        },
         OP_LDY_IMM => {
             let value = cpu.fetch();
             cpu.y = value;
             cpu.update_zero_and_negative_flags(cpu.y);
         },
+        OP_LDY_ZP => {
+            let address = zero_page(cpu);
+            cpu.y = cpu.read(address);
+            cpu.update_zero_and_negative_flags(cpu.y);
+        },
+        OP_LDY_ZPX => {
+            let address = zero_page_x(cpu);
+            cpu.y = cpu.read(address);
+            cpu.update_zero_and_negative_flags(cpu.y);
+        },
+        OP_LDY_ABS => {
+            let address = absolute(cpu);
+            cpu.y = cpu.read(address);
+            cpu.update_zero_and_negative_flags(cpu.y);
+        },
+        OP_LDY_ABSX => {
+            let (address, page_crossed) = absolute_x(cpu);
+            cycles += page_crossed as u32;
+            cpu.y = cpu.read(address);
+            cpu.update_zero_and_negative_flags(cpu.y);
+        },
         OP_STA_ZP => {
             let address = cpu.fetch() as u16;
             cpu.write(address, cpu.a);
@@ -99,14 +313,46 @@ pub fn execute(cpu: &mut CPU, opcode: u8) {
             let address = (high << 8) | low;
             cpu.write(address, cpu.a);
         },
+        OP_STA_ABSX => {
+            let (address, _) = absolute_x(cpu);
+            cpu.write(address, cpu.a);
+        },
+        OP_STA_ABSY => {
+            let (address, _) = absolute_y(cpu);
+            cpu.write(address, cpu.a);
+        },
+        OP_STA_INDX => {
+            let address = indexed_indirect(cpu);
+            cpu.write(address, cpu.a);
+        },
+        OP_STA_INDY => {
+            let (address, _) = indirect_indexed(cpu);
+            cpu.write(address, cpu.a);
+        },
         OP_STX_ZP => {
             let address = cpu.fetch() as u16;
             cpu.write(address, cpu.x);
         },
+        OP_STX_ZPY => {
+            let address = zero_page_y(cpu);
+            cpu.write(address, cpu.x);
+        },
+        OP_STX_ABS => {
+            let address = absolute(cpu);
+            cpu.write(address, cpu.x);
+        },
         OP_STY_ZP => {
             let address = cpu.fetch() as u16;
             cpu.write(address, cpu.y);
         },
+        OP_STY_ZPX => {
+            let address = zero_page_x(cpu);
+            cpu.write(address, cpu.y);
+        },
+        OP_STY_ABS => {
+            let address = absolute(cpu);
+            cpu.write(address, cpu.y);
+        },
         OP_TAX => {
             cpu.x = cpu.a;
             cpu.update_zero_and_negative_flags(cpu.x);
@@ -123,57 +369,411 @@ pub fn execute(cpu: &mut CPU, opcode: u8) {
             cpu.a = cpu.y;
             cpu.update_zero_and_negative_flags(cpu.a);
         },
+        OP_TSX => {
+            cpu.x = cpu.sp;
+            cpu.update_zero_and_negative_flags(cpu.x);
+        },
+        OP_TXS => {
+            // Unlike the other transfers, TXS leaves the flags alone
+            cpu.sp = cpu.x;
+        },
+        OP_PHA => {
+            cpu.push(cpu.a);
+        },
+        OP_PLA => {
+            cpu.a = cpu.pop();
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_PHP => {
+            cpu.push(cpu.status);
+        },
+        OP_PLP => {
+            cpu.status = cpu.pop() & !FLAG_BREAK;
+        },
         OP_ADC_IMM => {
             let value = cpu.fetch();
-            let carry = if cpu.get_flag(FLAG_CARRY) { 1 } else { 0 };
-            
-            let result = cpu.a as u16 + value as u16 + carry as u16;
-            let overflow = ((cpu.a ^ result as u8) & (value ^ result as u8) & 0x80) != 0;
-            
-            cpu.a = result as u8;
-            cpu.set_flag(FLAG_CARRY, result > 0xFF);
-            cpu.set_flag(FLAG_OVERFLOW, overflow);
-            cpu.update_zero_and_negative_flags(cpu.a);
+            add_with_carry(cpu, value);
+        },
+        OP_ADC_ZP => {
+            let address = zero_page(cpu);
+            let value = cpu.read(address);
+            add_with_carry(cpu, value);
+        },
+        OP_ADC_ZPX => {
+            let address = zero_page_x(cpu);
+            let value = cpu.read(address);
+            add_with_carry(cpu, value);
+        },
+        OP_ADC_ABS => {
+            let address = absolute(cpu);
+            let value = cpu.read(address);
+            add_with_carry(cpu, value);
+        },
+        OP_ADC_ABSX => {
+            let (address, page_crossed) = absolute_x(cpu);
+            cycles += page_crossed as u32;
+            let value = cpu.read(address);
+            add_with_carry(cpu, value);
+        },
+        OP_ADC_ABSY => {
+            let (address, page_crossed) = absolute_y(cpu);
+            cycles += page_crossed as u32;
+            let value = cpu.read(address);
+            add_with_carry(cpu, value);
+        },
+        OP_ADC_INDX => {
+            let address = indexed_indirect(cpu);
+            let value = cpu.read(address);
+            add_with_carry(cpu, value);
+        },
+        OP_ADC_INDY => {
+            let (address, page_crossed) = indirect_indexed(cpu);
+            cycles += page_crossed as u32;
+            let value = cpu.read(address);
+            add_with_carry(cpu, value);
         },
         OP_SBC_IMM => {
             let value = cpu.fetch();
-            let carry = if cpu.get_flag(FLAG_CARRY) { 0 } else { 1 };
-            
-            let result = cpu.a as i16 - value as i16 - carry as i16;
-            let overflow = ((cpu.a ^ value) & (cpu.a ^ result as u8) & 0x80) != 0;
-            
-            cpu.a = result as u8;
-            cpu.set_flag(FLAG_CARRY, result >= 0);
-            cpu.set_flag(FLAG_OVERFLOW, overflow);
-            cpu.update_zero_and_negative_flags(cpu.a);
+            subtract_with_carry(cpu, value);
+        },
+        OP_SBC_ZP => {
+            let address = zero_page(cpu);
+            let value = cpu.read(address);
+            subtract_with_carry(cpu, value);
+        },
+        OP_SBC_ZPX => {
+            let address = zero_page_x(cpu);
+            let value = cpu.read(address);
+            subtract_with_carry(cpu, value);
+        },
+        OP_SBC_ABS => {
+            let address = absolute(cpu);
+            let value = cpu.read(address);
+            subtract_with_carry(cpu, value);
+        },
+        OP_SBC_ABSX => {
+            let (address, page_crossed) = absolute_x(cpu);
+            cycles += page_crossed as u32;
+            let value = cpu.read(address);
+            subtract_with_carry(cpu, value);
+        },
+        OP_SBC_ABSY => {
+            let (address, page_crossed) = absolute_y(cpu);
+            cycles += page_crossed as u32;
+            let value = cpu.read(address);
+            subtract_with_carry(cpu, value);
+        },
+        OP_SBC_INDX => {
+            let address = indexed_indirect(cpu);
+            let value = cpu.read(address);
+            subtract_with_carry(cpu, value);
+        },
+        OP_SBC_INDY => {
+            let (address, page_crossed) = indirect_indexed(cpu);
+            cycles += page_crossed as u32;
+            let value = cpu.read(address);
+            subtract_with_carry(cpu, value);
         },
         OP_AND_IMM => {
             let value = cpu.fetch();
             cpu.a &= value;
             cpu.update_zero_and_negative_flags(cpu.a);
         },
+        OP_AND_ZP => {
+            let address = zero_page(cpu);
+            let value = cpu.read(address);
+            cpu.a &= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_AND_ZPX => {
+            let address = zero_page_x(cpu);
+            let value = cpu.read(address);
+            cpu.a &= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_AND_ABS => {
+            let address = absolute(cpu);
+            let value = cpu.read(address);
+            cpu.a &= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_AND_ABSX => {
+            let (address, page_crossed) = absolute_x(cpu);
+            cycles += page_crossed as u32;
+            let value = cpu.read(address);
+            cpu.a &= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_AND_ABSY => {
+            let (address, page_crossed) = absolute_y(cpu);
+            cycles += page_crossed as u32;
+            let value = cpu.read(address);
+            cpu.a &= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_AND_INDX => {
+            let address = indexed_indirect(cpu);
+            let value = cpu.read(address);
+            cpu.a &= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_AND_INDY => {
+            let (address, page_crossed) = indirect_indexed(cpu);
+            cycles += page_crossed as u32;
+            let value = cpu.read(address);
+            cpu.a &= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
         OP_ORA_IMM => {
             let value = cpu.fetch();
             cpu.a |= value;
             cpu.update_zero_and_negative_flags(cpu.a);
         },
+        OP_ORA_ZP => {
+            let address = zero_page(cpu);
+            let value = cpu.read(address);
+            cpu.a |= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_ORA_ZPX => {
+            let address = zero_page_x(cpu);
+            let value = cpu.read(address);
+            cpu.a |= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_ORA_ABS => {
+            let address = absolute(cpu);
+            let value = cpu.read(address);
+            cpu.a |= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_ORA_ABSX => {
+            let (address, page_crossed) = absolute_x(cpu);
+            cycles += page_crossed as u32;
+            let value = cpu.read(address);
+            cpu.a |= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_ORA_ABSY => {
+            let (address, page_crossed) = absolute_y(cpu);
+            cycles += page_crossed as u32;
+            let value = cpu.read(address);
+            cpu.a |= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_ORA_INDX => {
+            let address = indexed_indirect(cpu);
+            let value = cpu.read(address);
+            cpu.a |= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_ORA_INDY => {
+            let (address, page_crossed) = indirect_indexed(cpu);
+            cycles += page_crossed as u32;
+            let value = cpu.read(address);
+            cpu.a |= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
         OP_EOR_IMM => {
             let value = cpu.fetch();
             cpu.a ^= value;
             cpu.update_zero_and_negative_flags(cpu.a);
         },
+        OP_EOR_ZP => {
+            let address = zero_page(cpu);
+            let value = cpu.read(address);
+            cpu.a ^= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_EOR_ZPX => {
+            let address = zero_page_x(cpu);
+            let value = cpu.read(address);
+            cpu.a ^= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_EOR_ABS => {
+            let address = absolute(cpu);
+            let value = cpu.read(address);
+            cpu.a ^= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_EOR_ABSX => {
+            let (address, page_crossed) = absolute_x(cpu);
+            cycles += page_crossed as u32;
+            let value = cpu.read(address);
+            cpu.a ^= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_EOR_ABSY => {
+            let (address, page_crossed) = absolute_y(cpu);
+            cycles += page_crossed as u32;
+            let value = cpu.read(address);
+            cpu.a ^= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_EOR_INDX => {
+            let address = indexed_indirect(cpu);
+            let value = cpu.read(address);
+            cpu.a ^= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
+        OP_EOR_INDY => {
+            let (address, page_crossed) = indirect_indexed(cpu);
+            cycles += page_crossed as u32;
+            let value = cpu.read(address);
+            cpu.a ^= value;
+            cpu.update_zero_and_negative_flags(cpu.a);
+        },
         OP_INC_ZP => {
             let address = cpu.fetch() as u16;
             let value = cpu.read(address).wrapping_add(1);
             cpu.write(address, value);
             cpu.update_zero_and_negative_flags(value);
         },
+        OP_INC_ZPX => {
+            let address = zero_page_x(cpu);
+            let value = cpu.read(address).wrapping_add(1);
+            cpu.write(address, value);
+            cpu.update_zero_and_negative_flags(value);
+        },
+        OP_INC_ABS => {
+            let address = absolute(cpu);
+            let value = cpu.read(address).wrapping_add(1);
+            cpu.write(address, value);
+            cpu.update_zero_and_negative_flags(value);
+        },
+        OP_INC_ABSX => {
+            let (address, _) = absolute_x(cpu);
+            let value = cpu.read(address).wrapping_add(1);
+            cpu.write(address, value);
+            cpu.update_zero_and_negative_flags(value);
+        },
         OP_DEC_ZP => {
             let address = cpu.fetch() as u16;
             let value = cpu.read(address).wrapping_sub(1);
             cpu.write(address, value);
             cpu.update_zero_and_negative_flags(value);
         },
+        OP_DEC_ZPX => {
+            let address = zero_page_x(cpu);
+            let value = cpu.read(address).wrapping_sub(1);
+            cpu.write(address, value);
+            cpu.update_zero_and_negative_flags(value);
+        },
+        OP_DEC_ABS => {
+            let address = absolute(cpu);
+            let value = cpu.read(address).wrapping_sub(1);
+            cpu.write(address, value);
+            cpu.update_zero_and_negative_flags(value);
+        },
+        OP_ASL_ACC => {
+            cpu.a = shift_left(cpu, cpu.a);
+        },
+        OP_ASL_ZP => {
+            let address = zero_page(cpu);
+            let value = cpu.read(address);
+            let result = shift_left(cpu, value);
+            cpu.write(address, result);
+        },
+        OP_ASL_ZPX => {
+            let address = zero_page_x(cpu);
+            let value = cpu.read(address);
+            let result = shift_left(cpu, value);
+            cpu.write(address, result);
+        },
+        OP_ASL_ABS => {
+            let address = absolute(cpu);
+            let value = cpu.read(address);
+            let result = shift_left(cpu, value);
+            cpu.write(address, result);
+        },
+        OP_ASL_ABSX => {
+            let (address, _) = absolute_x(cpu);
+            let value = cpu.read(address);
+            let result = shift_left(cpu, value);
+            cpu.write(address, result);
+        },
+        OP_LSR_ACC => {
+            cpu.a = shift_right(cpu, cpu.a);
+        },
+        OP_LSR_ZP => {
+            let address = zero_page(cpu);
+            let value = cpu.read(address);
+            let result = shift_right(cpu, value);
+            cpu.write(address, result);
+        },
+        OP_LSR_ZPX => {
+            let address = zero_page_x(cpu);
+            let value = cpu.read(address);
+            let result = shift_right(cpu, value);
+            cpu.write(address, result);
+        },
+        OP_LSR_ABS => {
+            let address = absolute(cpu);
+            let value = cpu.read(address);
+            let result = shift_right(cpu, value);
+            cpu.write(address, result);
+        },
+        OP_LSR_ABSX => {
+            let (address, _) = absolute_x(cpu);
+            let value = cpu.read(address);
+            let result = shift_right(cpu, value);
+            cpu.write(address, result);
+        },
+        OP_ROL_ACC => {
+            cpu.a = rotate_left(cpu, cpu.a);
+        },
+        OP_ROL_ZP => {
+            let address = zero_page(cpu);
+            let value = cpu.read(address);
+            let result = rotate_left(cpu, value);
+            cpu.write(address, result);
+        },
+        OP_ROL_ZPX => {
+            let address = zero_page_x(cpu);
+            let value = cpu.read(address);
+            let result = rotate_left(cpu, value);
+            cpu.write(address, result);
+        },
+        OP_ROL_ABS => {
+            let address = absolute(cpu);
+            let value = cpu.read(address);
+            let result = rotate_left(cpu, value);
+            cpu.write(address, result);
+        },
+        OP_ROL_ABSX => {
+            let (address, _) = absolute_x(cpu);
+            let value = cpu.read(address);
+            let result = rotate_left(cpu, value);
+            cpu.write(address, result);
+        },
+        OP_ROR_ACC => {
+            cpu.a = rotate_right(cpu, cpu.a);
+        },
+        OP_ROR_ZP => {
+            let address = zero_page(cpu);
+            let value = cpu.read(address);
+            let result = rotate_right(cpu, value);
+            cpu.write(address, result);
+        },
+        OP_ROR_ZPX => {
+            let address = zero_page_x(cpu);
+            let value = cpu.read(address);
+            let result = rotate_right(cpu, value);
+            cpu.write(address, result);
+        },
+        OP_ROR_ABS => {
+            let address = absolute(cpu);
+            let value = cpu.read(address);
+            let result = rotate_right(cpu, value);
+            cpu.write(address, result);
+        },
+        OP_ROR_ABSX => {
+            let (address, _) = absolute_x(cpu);
+            let value = cpu.read(address);
+            let result = rotate_right(cpu, value);
+            cpu.write(address, result);
+        },
         OP_INX => {
             cpu.x = cpu.x.wrapping_add(1);
             cpu.update_zero_and_negative_flags(cpu.x);
@@ -192,27 +792,94 @@ pub fn execute(cpu: &mut CPU, opcode: u8) {
         },
         OP_CMP_IMM => {
             let value = cpu.fetch();
-            let result = cpu.a.wrapping_sub(value);
-            cpu.set_flag(FLAG_CARRY, cpu.a >= value);
-            cpu.update_zero_and_negative_flags(result);
+            compare(cpu, cpu.a, value);
+        },
+        OP_CMP_ZP => {
+            let address = zero_page(cpu);
+            let value = cpu.read(address);
+            compare(cpu, cpu.a, value);
+        },
+        OP_CMP_ZPX => {
+            let address = zero_page_x(cpu);
+            let value = cpu.read(address);
+            compare(cpu, cpu.a, value);
+        },
+        OP_CMP_ABS => {
+            let address = absolute(cpu);
+            let value = cpu.read(address);
+            compare(cpu, cpu.a, value);
+        },
+        OP_CMP_ABSX => {
+            let (address, page_crossed) = absolute_x(cpu);
+            cycles += page_crossed as u32;
+            let value = cpu.read(address);
+            compare(cpu, cpu.a, value);
+        },
+        OP_CMP_ABSY => {
+            let (address, page_crossed) = absolute_y(cpu);
+            cycles += page_crossed as u32;
+            let value = cpu.read(address);
+            compare(cpu, cpu.a, value);
+        },
+        OP_CMP_INDX => {
+            let address = indexed_indirect(cpu);
+            let value = cpu.read(address);
+            compare(cpu, cpu.a, value);
+        },
+        OP_CMP_INDY => {
+            let (address, page_crossed) = indirect_indexed(cpu);
+            cycles += page_crossed as u32;
+            let value = cpu.read(address);
+            compare(cpu, cpu.a, value);
         },
         OP_CPX_IMM => {
             let value = cpu.fetch();
-            let result = cpu.x.wrapping_sub(value);
-            cpu.set_flag(FLAG_CARRY, cpu.x >= value);
-            cpu.update_zero_and_negative_flags(result);
+            compare(cpu, cpu.x, value);
+        },
+        OP_CPX_ZP => {
+            let address = zero_page(cpu);
+            let value = cpu.read(address);
+            compare(cpu, cpu.x, value);
+        },
+        OP_CPX_ABS => {
+            let address = absolute(cpu);
+            let value = cpu.read(address);
+            compare(cpu, cpu.x, value);
         },
         OP_CPY_IMM => {
             let value = cpu.fetch();
-            let result = cpu.y.wrapping_sub(value);
-            cpu.set_flag(FLAG_CARRY, cpu.y >= value);
-            cpu.update_zero_and_negative_flags(result);
+            compare(cpu, cpu.y, value);
+        },
+        OP_CPY_ZP => {
+            let address = zero_page(cpu);
+            let value = cpu.read(address);
+            compare(cpu, cpu.y, value);
+        },
+        OP_CPY_ABS => {
+            let address = absolute(cpu);
+            let value = cpu.read(address);
+            compare(cpu, cpu.y, value);
+        },
+        OP_BIT_ZP => {
+            let address = zero_page(cpu);
+            let value = cpu.read(address);
+            bit_test(cpu, value);
+        },
+        OP_BIT_ABS => {
+            let address = absolute(cpu);
+            let value = cpu.read(address);
+            bit_test(cpu, value);
         },
         OP_JMP_ABS => {
             let low = cpu.fetch() as u16;
             let high = cpu.fetch() as u16;
             cpu.pc = (high << 8) | low;
         },
+        OP_JMP_IND => {
+            // Unlike the 6502, a pointer at $xxFF reads its high byte from the next page
+            let pointer = absolute(cpu);
+            cpu.pc = cpu.read_word(pointer);
+        },
         OP_JSR_ABS => {
             let low = cpu.fetch() as u16;
             let high = cpu.fetch() as u16;
@@ -228,62 +895,133 @@ pub fn execute(cpu: &mut CPU, opcode: u8) {
             let high = cpu.pop() as u16;
             cpu.pc = ((high << 8) | low) + 1;
         },
+        OP_RTI => {
+            cpu.status = cpu.pop() & !FLAG_BREAK;
+            let low = cpu.pop() as u16;
+            let high = cpu.pop() as u16;
+            cpu.pc = (high << 8) | low;
+        },
         OP_BEQ => {
-            let offset = cpu.fetch() as i8;
-            if cpu.get_flag(FLAG_ZERO) {
-                let old_pc = cpu.pc;
-                cpu.pc = cpu.pc.wrapping_add(offset as u16);
-            }
+            let condition = cpu.get_flag(FLAG_ZERO);
+            cycles += branch(cpu, condition);
         },
         OP_BNE => {
-            let offset = cpu.fetch() as i8;
-            if !cpu.get_flag(FLAG_ZERO) {
-                let old_pc = cpu.pc;
-                cpu.pc = cpu.pc.wrapping_add(offset as u16);
-            }
+            let condition = !cpu.get_flag(FLAG_ZERO);
+            cycles += branch(cpu, condition);
         },
         OP_BCS => {
-            let offset = cpu.fetch() as i8;
-            if cpu.get_flag(FLAG_CARRY) {
-                let old_pc = cpu.pc;
-                cpu.pc = cpu.pc.wrapping_add(offset as u16);
-            }
+            let condition = cpu.get_flag(FLAG_CARRY);
+            cycles += branch(cpu, condition);
         },
         OP_BCC => {
-            let offset = cpu.fetch() as i8;
-            if !cpu.get_flag(FLAG_CARRY) {
-                let old_pc = cpu.pc;
-                cpu.pc = cpu.pc.wrapping_add(offset as u16);
-            }
+            let condition = !cpu.get_flag(FLAG_CARRY);
+            cycles += branch(cpu, condition);
         },
         OP_BMI => {
-            let offset = cpu.fetch() as i8;
-            if cpu.get_flag(FLAG_NEGATIVE) {
-                let old_pc = cpu.pc;
-                cpu.pc = cpu.pc.wrapping_add(offset as u16);
-            }
+            let condition = cpu.get_flag(FLAG_NEGATIVE);
+            cycles += branch(cpu, condition);
         },
         OP_BPL => {
-            let offset = cpu.fetch() as i8;
-            if !cpu.get_flag(FLAG_NEGATIVE) {
-                let old_pc = cpu.pc;
-                cpu.pc = cpu.pc.wrapping_add(offset as u16);
-            }
+            let condition = !cpu.get_flag(FLAG_NEGATIVE);
+            cycles += branch(cpu, condition);
+        },
+        OP_BVS => {
+            let condition = cpu.get_flag(FLAG_OVERFLOW);
+            cycles += branch(cpu, condition);
+        },
+        OP_BVC => {
+            let condition = !cpu.get_flag(FLAG_OVERFLOW);
+            cycles += branch(cpu, condition);
+        },
+        OP_CLC => {
+            cpu.set_flag(FLAG_CARRY, false);
+        },
+        OP_SEC => {
+            cpu.set_flag(FLAG_CARRY, true);
+        },
+        OP_CLV => {
+            cpu.set_flag(FLAG_OVERFLOW, false);
+        },
+        OP_SEI => {
+            cpu.set_flag(FLAG_INTERRUPT_DISABLE, true);
+        },
+        OP_CLI => {
+            cpu.set_flag(FLAG_INTERRUPT_DISABLE, false);
+        },
+        OP_SED => {
+            cpu.set_flag(FLAG_DECIMAL, true);
+        },
+        OP_CLD => {
+            cpu.set_flag(FLAG_DECIMAL, false);
         },
         OP_NOP => {
             // No operation
         },
         OP_BRK => {
             // Break / Force Interrupt
-            // In our simple emulator, we'll just set the program counter to the next instruction
-            cpu.fetch(); // Skip the padding byte
+            // Skip the padding byte so RTI resumes after it, then enter the IRQ handler
+            cpu.fetch();
+            cpu.interrupt(IRQ_VECTOR, true);
+        },
+        OP_MUL => {
+            // 8x8 -> 16 bit multiply: low byte in A, high byte in X
+            let product = cpu.a as u16 * cpu.y as u16;
+            cpu.a = product as u8;
+            cpu.x = (product >> 8) as u8;
+            // Z and N reflect the whole 16 bit product, C is set if it didn't fit in A
+            cpu.set_flag(FLAG_ZERO, product == 0);
+            cpu.set_flag(FLAG_NEGATIVE, product & 0x8000 != 0);
+            cpu.set_flag(FLAG_CARRY, product > 0xFF);
+        },
+        OP_DIV => {
+            // 16/8 bit divide of X:A by Y: quotient in A, remainder in X
+            let dividend = ((cpu.x as u16) << 8) | cpu.a as u16;
+            let divisor = cpu.y as u16;
+            if divisor == 0 || dividend / divisor > 0xFF {
+                // Divide by zero or quotient overflow: set V and leave the registers alone
+                cpu.set_flag(FLAG_OVERFLOW, true);
+            } else {
+                cpu.a = (dividend / divisor) as u8;
+                cpu.x = (dividend % divisor) as u8;
+                cpu.set_flag(FLAG_OVERFLOW, false);
+                cpu.update_zero_and_negative_flags(cpu.a);
+                // C is set if there was a remainder
+                cpu.set_flag(FLAG_CARRY, cpu.x != 0);
+            }
+        },
+        OP_MOV => {
+            // Copy len bytes from src to dst, 2 cycles per byte (a read and a write).
+            // Overlapping blocks are copied as if through a temporary buffer.
+            let params = cpu.fetch();
+            let (src, dst, len) = read_block_params(cpu, params);
+            if dst.wrapping_sub(src) < len {
+                // dst starts inside the source block, copy from the end backwards
+                for i in (0..len).rev() {
+                    let value = cpu.read(src.wrapping_add(i));
+                    cpu.write(dst.wrapping_add(i), value);
+                }
+            } else {
+                for i in 0..len {
+                    let value = cpu.read(src.wrapping_add(i));
+                    cpu.write(dst.wrapping_add(i), value);
+                }
+            }
+            cycles += 2 * len as u32;
+        },
+        OP_FIL => {
+            // Write A to len bytes starting at dst, 1 cycle per byte. src is ignored.
+            let params = cpu.fetch();
+            let (_, dst, len) = read_block_params(cpu, params);
+            for i in 0..len {
+                cpu.write(dst.wrapping_add(i), cpu.a);
+            }
+            cycles += len as u32;
         },
         OP_DBG => {
-            println!("DEBUG INSTRUCTION CALLED");
-            // Print out value
-            let address = cpu.fetch() as u16;
-            let value = cpu.read(address); 
-            println!("HELIOS DEBUG: Value {} @ {}", value, address);
+            // Report a zero page value to the host
+            let addr = cpu.fetch() as u16;
+            let value = cpu.read(addr);
+            event = Some(CpuEvent::Debug { addr, value });
         },
         OP_SND => {
             // Custom sound opcode
@@ -291,18 +1029,274 @@ pub fn execute(cpu: &mut CPU, opcode: u8) {
             // - CC is the channel number (0-3)
             // - NNNNNN is the MIDI note (0-63)
             let sound_data = cpu.fetch();
-            let audio_address = 0xFC00 | (sound_data & 0xFF) as u16;
+            let audio_address = 0xFC00 | sound_data as u16;
             cpu.write(audio_address, sound_data);
-            println!("Got SND Instruction. Writing {} to {}", sound_data, audio_address);
+            event = Some(CpuEvent::Sound { byte: sound_data });
         },
         OP_HLT => {
             // Halt the CPU
             cpu.halt();
+            event = Some(CpuEvent::Halted);
         },
         _ => {
-            // Unknown opcode
-            println!("Unknown opcode: {:02X} at address {:04X}", opcode, cpu.pc - 1);
-            cpu.halt();
+            // Unknown opcode, handled according to the CPU's illegal opcode policy
+            let pc = cpu.pc.wrapping_sub(1);
+            cycles = cpu.illegal_opcode(opcode);
+            event = Some(CpuEvent::IllegalOpcode { pc, op: opcode });
         }
     }
+    
+    StepResult { cycles, event }
+}
+
+// Addressing mode helpers. Each one fetches the operand bytes and returns the
+// effective address the instruction should read from or write to.
+
+fn zero_page<B: Bus>(cpu: &mut CPU<B>) -> u16 {
+    cpu.fetch() as u16
+}
+
+fn zero_page_x<B: Bus>(cpu: &mut CPU<B>) -> u16 {
+    cpu.fetch().wrapping_add(cpu.x) as u16
+}
+
+fn zero_page_y<B: Bus>(cpu: &mut CPU<B>) -> u16 {
+    cpu.fetch().wrapping_add(cpu.y) as u16
+}
+
+fn absolute<B: Bus>(cpu: &mut CPU<B>) -> u16 {
+    let low = cpu.fetch() as u16;
+    let high = cpu.fetch() as u16;
+    (high << 8) | low
+}
+
+// The indexed helpers also report whether indexing crossed a page boundary,
+// which costs read instructions an extra cycle.
+
+fn absolute_x<B: Bus>(cpu: &mut CPU<B>) -> (u16, bool) {
+    let base = absolute(cpu);
+    let address = base.wrapping_add(cpu.x as u16);
+    (address, page_crossed(base, address))
+}
+
+fn absolute_y<B: Bus>(cpu: &mut CPU<B>) -> (u16, bool) {
+    let base = absolute(cpu);
+    let address = base.wrapping_add(cpu.y as u16);
+    (address, page_crossed(base, address))
+}
+
+// (zp,X): the pointer lives at zp+X, wrapping inside the zero page
+fn indexed_indirect<B: Bus>(cpu: &mut CPU<B>) -> u16 {
+    let pointer = cpu.fetch().wrapping_add(cpu.x);
+    read_zero_page_pointer(cpu, pointer)
+}
+
+// (zp),Y: the pointer lives at zp and Y is added to the address it holds
+fn indirect_indexed<B: Bus>(cpu: &mut CPU<B>) -> (u16, bool) {
+    let pointer = cpu.fetch();
+    let base = read_zero_page_pointer(cpu, pointer);
+    let address = base.wrapping_add(cpu.y as u16);
+    (address, page_crossed(base, address))
+}
+
+fn page_crossed(base: u16, address: u16) -> bool {
+    (base & 0xFF00) != (address & 0xFF00)
+}
+
+fn read_zero_page_pointer<B: Bus>(cpu: &mut CPU<B>, pointer: u8) -> u16 {
+    let low = cpu.read(pointer as u16) as u16;
+    let high = cpu.read(pointer.wrapping_add(1) as u16) as u16;
+    (high << 8) | low
+}
+
+// Reads the (source, destination, length) parameter block of MOV/FIL
+fn read_block_params<B: Bus>(cpu: &mut CPU<B>, params: u8) -> (u16, u16, u16) {
+    let src = read_zero_page_pointer(cpu, params);
+    let dst = read_zero_page_pointer(cpu, params.wrapping_add(2));
+    let len = read_zero_page_pointer(cpu, params.wrapping_add(4));
+    (src, dst, len)
+}
+
+// ALU helpers shared by every addressing mode of an instruction.
+// In decimal mode ADC/SBC treat each nibble as a BCD digit: carry and the
+// Z/N flags follow the decimal result, V is still computed on the binary sum.
+
+fn add_with_carry<B: Bus>(cpu: &mut CPU<B>, value: u8) {
+    let carry = if cpu.get_flag(FLAG_CARRY) { 1 } else { 0 };
+
+    let result = cpu.a as u16 + value as u16 + carry as u16;
+    let overflow = ((cpu.a ^ result as u8) & (value ^ result as u8) & 0x80) != 0;
+
+    if cpu.get_flag(FLAG_DECIMAL) {
+        let mut low = (cpu.a & 0x0F) as u16 + (value & 0x0F) as u16 + carry as u16;
+        let mut high = (cpu.a >> 4) as u16 + (value >> 4) as u16;
+        if low > 9 {
+            low += 6;
+            high += 1;
+        }
+        if high > 9 {
+            high += 6;
+        }
+        
+        cpu.a = ((high << 4) | (low & 0x0F)) as u8;
+        cpu.set_flag(FLAG_CARRY, high > 0x0F);
+    } else {
+        cpu.a = result as u8;
+        cpu.set_flag(FLAG_CARRY, result > 0xFF);
+    }
+    cpu.set_flag(FLAG_OVERFLOW, overflow);
+    cpu.update_zero_and_negative_flags(cpu.a);
+}
+
+fn subtract_with_carry<B: Bus>(cpu: &mut CPU<B>, value: u8) {
+    let carry = if cpu.get_flag(FLAG_CARRY) { 0 } else { 1 };
+
+    let result = cpu.a as i16 - value as i16 - carry as i16;
+    let overflow = ((cpu.a ^ value) & (cpu.a ^ result as u8) & 0x80) != 0;
+
+    if cpu.get_flag(FLAG_DECIMAL) {
+        let mut low = (cpu.a & 0x0F) as i16 - (value & 0x0F) as i16 - carry as i16;
+        let mut high = (cpu.a >> 4) as i16 - (value >> 4) as i16;
+        if low < 0 {
+            low -= 6;
+            high -= 1;
+        }
+        if high < 0 {
+            high -= 6;
+        }
+        
+        cpu.a = ((high << 4) | (low & 0x0F)) as u8;
+    } else {
+        cpu.a = result as u8;
+    }
+    cpu.set_flag(FLAG_CARRY, result >= 0);
+    cpu.set_flag(FLAG_OVERFLOW, overflow);
+    cpu.update_zero_and_negative_flags(cpu.a);
+}
+
+fn compare<B: Bus>(cpu: &mut CPU<B>, register: u8, value: u8) {
+    let result = register.wrapping_sub(value);
+    cpu.set_flag(FLAG_CARRY, register >= value);
+    cpu.update_zero_and_negative_flags(result);
+}
+
+// Relative branch: always fetches the offset, returns the extra cycles spent
+// (one when taken, one more when the target lands on another page)
+fn branch<B: Bus>(cpu: &mut CPU<B>, condition: bool) -> u32 {
+    let offset = cpu.fetch() as i8;
+    if !condition {
+        return 0;
+    }
+    
+    let old_pc = cpu.pc;
+    cpu.pc = cpu.pc.wrapping_add(offset as u16);
+    if page_crossed(old_pc, cpu.pc) { 2 } else { 1 }
+}
+
+// BIT: Z from A AND value, N and V copied straight from bits 7 and 6 of value
+fn bit_test<B: Bus>(cpu: &mut CPU<B>, value: u8) {
+    cpu.set_flag(FLAG_ZERO, (cpu.a & value) == 0);
+    cpu.set_flag(FLAG_NEGATIVE, (value & 0x80) != 0);
+    cpu.set_flag(FLAG_OVERFLOW, (value & 0x40) != 0);
+}
+
+// Shift helpers: the bit shifted out lands in FLAG_CARRY and the result is returned
+
+fn shift_left<B: Bus>(cpu: &mut CPU<B>, value: u8) -> u8 {
+    let result = value << 1;
+    cpu.set_flag(FLAG_CARRY, (value & 0x80) != 0);
+    cpu.update_zero_and_negative_flags(result);
+    result
+}
+
+fn shift_right<B: Bus>(cpu: &mut CPU<B>, value: u8) -> u8 {
+    let result = value >> 1;
+    cpu.set_flag(FLAG_CARRY, (value & 0x01) != 0);
+    cpu.update_zero_and_negative_flags(result);
+    result
+}
+
+fn rotate_left<B: Bus>(cpu: &mut CPU<B>, value: u8) -> u8 {
+    let carry_in = if cpu.get_flag(FLAG_CARRY) { 0x01 } else { 0x00 };
+    let result = (value << 1) | carry_in;
+    cpu.set_flag(FLAG_CARRY, (value & 0x80) != 0);
+    cpu.update_zero_and_negative_flags(result);
+    result
+}
+
+fn rotate_right<B: Bus>(cpu: &mut CPU<B>, value: u8) -> u8 {
+    let carry_in = if cpu.get_flag(FLAG_CARRY) { 0x80 } else { 0x00 };
+    let result = (value >> 1) | carry_in;
+    cpu.set_flag(FLAG_CARRY, (value & 0x01) != 0);
+    cpu.update_zero_and_negative_flags(result);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{CpuError, IllegalOpcodePolicy};
+    use crate::memory::Memory;
+
+    // Runs `op` in decimal mode with the given A, operand and carry in, returning
+    // A and the C, Z and N flags afterwards
+    fn decimal(op: fn(&mut CPU<Memory>, u8), a: u8, value: u8, carry: bool) -> (u8, bool, bool, bool) {
+        let mut cpu = CPU::new(Memory::new());
+        cpu.set_flag(FLAG_DECIMAL, true);
+        cpu.set_flag(FLAG_CARRY, carry);
+        cpu.a = a;
+        op(&mut cpu, value);
+        (cpu.a, cpu.get_flag(FLAG_CARRY), cpu.get_flag(FLAG_ZERO), cpu.get_flag(FLAG_NEGATIVE))
+    }
+
+    #[test]
+    fn illegal_opcode_nop_reports_and_continues() {
+        let mut cpu = CPU::new(Memory::new());
+        cpu.illegal_opcode_policy = IllegalOpcodePolicy::Nop;
+        cpu.bus.write(0x0000, 0x03); // Not a Helios opcode
+        cpu.bus.write(0x0001, OP_LDA_IMM);
+        cpu.bus.write(0x0002, 0x42);
+
+        let result = cpu.step();
+        assert_eq!(result.event, Some(CpuEvent::IllegalOpcode { pc: 0x0000, op: 0x03 }));
+        assert!(!cpu.halted);
+        assert_eq!(cpu.last_error, Some(CpuError::IllegalOpcode { pc: 0x0000, opcode: 0x03 }));
+
+        cpu.step();
+        assert_eq!(cpu.a, 0x42);
+    }
+
+    #[test]
+    fn decimal_add() {
+        assert_eq!(decimal(add_with_carry, 0x15, 0x27, false), (0x42, false, false, false));
+        assert_eq!(decimal(add_with_carry, 0x15, 0x27, true), (0x43, false, false, false)); // Carry in
+        assert_eq!(decimal(add_with_carry, 0x58, 0x46, false), (0x04, true, false, false)); // Carry out
+        assert_eq!(decimal(add_with_carry, 0x45, 0x45, false), (0x90, false, false, true));
+    }
+
+    #[test]
+    fn decimal_add_wraps_to_zero() {
+        assert_eq!(decimal(add_with_carry, 0x99, 0x01, false), (0x00, true, true, false));
+    }
+
+    #[test]
+    fn decimal_add_invalid_digits() {
+        // Digits above 9 are adjusted like any other low digit that overflows
+        assert_eq!(decimal(add_with_carry, 0x0F, 0x01, false), (0x16, false, false, false));
+        assert_eq!(decimal(add_with_carry, 0xA0, 0x00, false), (0x00, true, true, false));
+    }
+
+    #[test]
+    fn decimal_subtract() {
+        assert_eq!(decimal(subtract_with_carry, 0x42, 0x13, true), (0x29, true, false, false));
+        assert_eq!(decimal(subtract_with_carry, 0x42, 0x13, false), (0x28, true, false, false)); // Borrow in
+        assert_eq!(decimal(subtract_with_carry, 0x12, 0x21, true), (0x91, false, false, true)); // Borrow out
+        assert_eq!(decimal(subtract_with_carry, 0x25, 0x25, true), (0x00, true, true, false));
+    }
+
+    #[test]
+    fn decimal_subtract_invalid_digits() {
+        assert_eq!(decimal(subtract_with_carry, 0x1A, 0x01, true), (0x19, true, false, false));
+        assert_eq!(decimal(subtract_with_carry, 0x10, 0x0B, true), (0x0F, true, false, false));
+    }
 }
@@ -0,0 +1,14 @@
+// src/lib.rs
+// Helios as a library: the CPU, memory, assembler and a `Machine` tying them
+// together, so tools and frontends can embed the console.
+pub mod bus;
+pub mod compiler;
+pub mod cpu;
+mod expression;
+mod isa;
+pub mod machine;
+pub mod memory;
+pub mod rng;
+pub mod timer;
+
+pub use machine::Machine;
@@ -0,0 +1,167 @@
+// src/machine.rs
+use std::time::{Duration, Instant};
+
+use crate::cpu::{CpuError, CpuEvent, IllegalOpcodePolicy, StepResult, CPU};
+use crate::memory::{Memory, RomWriteProtection};
+
+pub const FRAME_RATE: u64 = 60;
+pub const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / FRAME_RATE);
+
+// Cycles run between checks of the host clock when the CPU is unthrottled
+const UNTHROTTLED_SLICE: u64 = 10_000;
+
+// A complete Helios console: the CPU and everything on its bus. Frontends drive
+// it with `step` or `run_frame` and read the display/audio buffers between frames.
+pub struct Machine {
+    cpu: CPU<Memory>,
+    overshoot: u64, // Cycles the last frame ran past its budget
+    remainder: u64, // clock_hz % FRAME_RATE left over from earlier frames, in 1/FRAME_RATE cycles
+    events: Vec<CpuEvent>, // Events raised during the last frame
+    turbo: bool,           // Run unthrottled regardless of the CPU clock rate
+}
+
+impl Machine {
+    pub fn new() -> Self {
+        Self {
+            cpu: CPU::new(Memory::new()),
+            overshoot: 0,
+            remainder: 0,
+            events: Vec::new(),
+            turbo: false,
+        }
+    }
+    
+    // Load a ROM image and reset the CPU so it starts running it. Fails if the
+    // image has data in the address range between the ROM and the vectors.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), String> {
+        self.cpu.bus.load_program(rom)?;
+        self.reset();
+        Ok(())
+    }
+    
+    pub fn reset(&mut self) {
+        self.cpu.bus.reset();
+        self.cpu.reset();
+        self.overshoot = 0;
+        self.remainder = 0;
+        self.events.clear();
+    }
+    
+    // Start at the address in the reset vector instead of $0000 (takes effect on reset)
+    pub fn set_use_reset_vector(&mut self, enabled: bool) {
+        self.cpu.use_reset_vector = enabled;
+    }
+    
+    pub fn set_rom_write_protection(&mut self, protection: RomWriteProtection) {
+        self.cpu.bus.set_rom_write_protection(protection);
+    }
+    
+    pub fn set_illegal_opcode_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.cpu.illegal_opcode_policy = policy;
+    }
+    
+    // Seed the hardware random number source (restarts its sequence)
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.cpu.bus.seed_rng(seed);
+    }
+    
+    // Set the CPU clock rate in Hz, or None to run as fast as the host allows.
+    // A rate of 0 would never run anything and is rejected.
+    pub fn set_clock_hz(&mut self, clock_hz: Option<u64>) -> Result<(), String> {
+        if clock_hz == Some(0) {
+            return Err("CPU clock rate must be at least 1 Hz".to_string());
+        }
+        self.cpu.set_clock_hz(clock_hz);
+        self.overshoot = 0;
+        self.remainder = 0;
+        Ok(())
+    }
+    
+    // Turbo runs the CPU unthrottled but still hands back one frame every
+    // FRAME_DURATION, so display and audio keep their normal pace
+    pub fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+        self.overshoot = 0;
+        self.remainder = 0;
+    }
+    
+    pub fn is_turbo(&self) -> bool {
+        self.turbo
+    }
+    
+    // Run a single instruction, returning the cycles it took and any event it raised
+    pub fn step(&mut self) -> StepResult {
+        self.cpu.step()
+    }
+    
+    // Run one video frame's worth of cycles, returning how many were actually run.
+    // Overshoot from the previous frame is taken off this one, and the cycles that
+    // don't divide evenly into frames are carried over, so time doesn't drift.
+    // Unthrottled (no clock rate, or turbo) the CPU runs for FRAME_DURATION of host
+    // time instead. The events raised during the frame are available from `events` afterwards.
+    pub fn run_frame(&mut self) -> u64 {
+        self.events.clear();
+        
+        let clock_hz = if self.turbo { None } else { self.cpu.clock_hz };
+        match clock_hz {
+            Some(clock_hz) => {
+                let total = clock_hz.saturating_add(self.remainder);
+                self.remainder = total % FRAME_RATE;
+                let frame_cycles = total / FRAME_RATE;
+                let budget = frame_cycles.saturating_sub(self.overshoot);
+                let spent = self.cpu.run_cycles(budget, &mut self.events);
+                // At very low clock rates one instruction can overshoot several frames
+                self.overshoot = (self.overshoot + spent).saturating_sub(frame_cycles);
+                spent
+            },
+            None => {
+                let deadline = Instant::now() + FRAME_DURATION;
+                let mut spent = 0;
+                while !self.cpu.halted && Instant::now() < deadline {
+                    spent += self.cpu.run_cycles(UNTHROTTLED_SLICE, &mut self.events);
+                }
+                spent
+            },
+        }
+    }
+    
+    // Events raised during the last `run_frame`, in the order they happened
+    pub fn events(&self) -> &[CpuEvent] {
+        &self.events
+    }
+    
+    pub fn is_halted(&self) -> bool {
+        self.cpu.halted
+    }
+    
+    // The most recent CPU error (e.g. the illegal opcode that halted it), if any
+    pub fn last_error(&self) -> Option<CpuError> {
+        self.cpu.last_error
+    }
+    
+    pub fn cycles(&self) -> u64 {
+        self.cpu.cycles
+    }
+    
+    pub fn cpu(&self) -> &CPU<Memory> {
+        &self.cpu
+    }
+    
+    pub fn cpu_mut(&mut self) -> &mut CPU<Memory> {
+        &mut self.cpu
+    }
+    
+    pub fn memory(&self) -> &Memory {
+        &self.cpu.bus
+    }
+    
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.cpu.bus
+    }
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
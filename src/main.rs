@@ -1,18 +1,17 @@
 // src/main.rs
-mod cpu;
-mod isa;
 mod display;
 mod audio;
-mod compiler;
-mod memory;
 
-use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 use std::fs::File;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::Path;
 use clap::{App, Arg};
+use helios::{compiler, Machine};
+use helios::cpu::{CpuEvent, IllegalOpcodePolicy};
+use helios::machine::FRAME_DURATION;
+use helios::memory::RomWriteProtection;
 
 fn main() {
     let matches = App::new("Helios")
@@ -30,28 +29,51 @@ fn main() {
                 .value_name("FILE")
                 .help("Assembly file to compile and run")
                 .takes_value(true))
+        .arg(Arg::with_name("reset-vector")
+                .long("reset-vector")
+                .help("Start execution at the address stored in the reset vector ($FFFC) instead of $0000"))
+        .arg(Arg::with_name("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("Seed for the hardware random number source, for reproducible runs")
+                .takes_value(true))
+        .arg(Arg::with_name("illegal-opcodes")
+                .long("illegal-opcodes")
+                .value_name("POLICY")
+                .help("What to do on an unknown opcode: halt, nop (warn and skip) or trap (interrupt through $FFF8)")
+                .possible_values(&["halt", "nop", "trap"])
+                .default_value("halt"))
+        .arg(Arg::with_name("protect-rom")
+                .long("protect-rom")
+                .value_name("MODE")
+                .help("Refuse writes to ROM ($0200-$7FFF): ignore them, log them, or break (log and halt)")
+                .possible_values(&["ignore", "log", "break"])
+                .takes_value(true))
+        .arg(Arg::with_name("clock")
+                .long("clock")
+                .value_name("HZ")
+                .help("CPU clock rate, e.g. 1000000, 4MHz, 500kHz or unlimited")
+                .takes_value(true)
+                .default_value("1MHz"))
+        .arg(Arg::with_name("turbo")
+                .long("turbo")
+                .help("Start in turbo mode (run unthrottled, toggle with Tab in the display window)"))
+        .arg(Arg::with_name("display")
+                .long("display")
+                .help("Open the display window (Esc quits, Tab toggles turbo mode)"))
         .get_matches();
 
-    // Initialize shared memory
-    let memory = Arc::new(Mutex::new(memory::Memory::new()));
-    
-    // Initialize CPU
-    let cpu = Arc::new(Mutex::new(cpu::CPU::new(Arc::clone(&memory))));
-    
     // Load ROM or compile assembly
-    if let Some(rom_path) = matches.value_of("rom") {
+    let program = if let Some(rom_path) = matches.value_of("rom") {
         let mut file = File::open(rom_path).expect("Failed to open ROM file");
         let mut rom_data = Vec::new();
         file.read_to_end(&mut rom_data).expect("Failed to read ROM file");
-        
-        memory.lock().unwrap().load_program(&rom_data);
+
+        rom_data
     } else if let Some(asm_path) = matches.value_of("assembly") {
-        let mut file = File::open(asm_path).expect("Failed to open assembly file");
-        let mut asm_content = String::new();
-        file.read_to_string(&mut asm_content).expect("Failed to read assembly file");
-        
-        match compiler::compile(&asm_content) {
-            Ok(binary) => memory.lock().unwrap().load_program(&binary),
+        // Compiling from the path lets .include and .incbin find files next to it
+        match compiler::compile_file(Path::new(asm_path)) {
+            Ok(binary) => binary,
             Err(err) => {
                 eprintln!("Compilation failed: {}", err);
                 return;
@@ -60,68 +82,128 @@ fn main() {
     } else {
         println!("No ROM or assembly file specified. Use --rom or --asm options.");
         return;
-    }
+    };
+
+    let mut machine = Machine::new();
+    machine.set_use_reset_vector(matches.is_present("reset-vector"));
+    let illegal_opcode_policy = match matches.value_of("illegal-opcodes") {
+        Some("nop") => IllegalOpcodePolicy::Nop,
+        Some("trap") => IllegalOpcodePolicy::Trap,
+        _ => IllegalOpcodePolicy::Halt,
+    };
+    machine.set_illegal_opcode_policy(illegal_opcode_policy);
+    machine.set_rom_write_protection(match matches.value_of("protect-rom") {
+        Some("ignore") => RomWriteProtection::Ignore,
+        Some("log") => RomWriteProtection::Log,
+        Some("break") => RomWriteProtection::Break,
+        _ => RomWriteProtection::Off,
+    });
+    // Seed the random number source, picking a seed if none was given. It's
+    // printed so a run can be reproduced later with --seed.
+    let seed = match matches.value_of("seed") {
+        Some(seed) => match seed.parse::<u64>() {
+            Ok(seed) => seed,
+            Err(_) => {
+                eprintln!("Invalid seed: {}", seed);
+                return;
+            }
+        },
+        None => {
+            let seed = rand::random::<u64>();
+            eprintln!("RNG seed: {}", seed);
+            seed
+        }
+    };
+    machine.seed_rng(seed);
     
-    // Start display thread
+    let clock = matches.value_of("clock").unwrap();
+    let clock_set = match parse_clock(clock) {
+        Some(clock_hz) => machine.set_clock_hz(clock_hz),
+        None => Err(format!("Invalid clock rate: {}", clock)),
+    };
+    if let Err(err) = clock_set {
+        eprintln!("{}", err);
+        return;
+    }
+    machine.set_turbo(matches.is_present("turbo"));
+    if let Err(err) = machine.load_rom(&program) {
+        eprintln!("Failed to load ROM: {}", err);
+        return;
+    }
+
+    // Start display and audio. The window is optional so ROMs can also run headless.
+    let mut display = if matches.is_present("display") { Some(display::Display::new()) } else { None };
     /*
-    let display_memory = Arc::clone(&memory);
-    let display_handle = thread::spawn(move || {
-        let mut display = display::Display::new(Arc::clone(&display_memory));
-        
-        let frame_duration = Duration::from_millis(33); // ~30 FPS
-        let mut last_frame = Instant::now();
+    let mut audio = audio::Audio::new();
+    */
+
+    // Run the machine a frame at a time, sleeping off whatever is left of each
+    // frame instead of sleeping after every instruction. Turbo and unlimited
+    // clocks fill the whole frame with work, so frames still come at FRAME_RATE.
+    let mut next_frame = Instant::now();
+
+    loop {
+        machine.run_frame();
         
-        loop {
-            let now = Instant::now();
-            let elapsed = now.duration_since(last_frame);
-            
-            if elapsed >= frame_duration {
-                display.update();
-                last_frame = now;
+        for event in machine.events() {
+            match event {
+                CpuEvent::Debug { addr, value } => println!("HELIOS DEBUG: Value {} @ {}", value, addr),
+                CpuEvent::IllegalOpcode { pc, op } => match illegal_opcode_policy {
+                    IllegalOpcodePolicy::Halt => eprintln!("Illegal opcode {:02X} at address {:04X}, halting", op, pc),
+                    IllegalOpcodePolicy::Nop => eprintln!("Warning: Illegal opcode {:02X} at address {:04X}, skipping it", op, pc),
+                    IllegalOpcodePolicy::Trap => eprintln!("Illegal opcode {:02X} at address {:04X}, trapping", op, pc),
+                },
+                CpuEvent::RomWrite { pc, address, value } => {
+                    eprintln!("Write of {:02X} to ROM at {:04X} by the instruction at {:04X}", value, address, pc);
+                },
+                CpuEvent::Halted | CpuEvent::Sound { .. } => {}
             }
-            
+        }
+
+        if let Some(display) = display.as_mut() {
+            display.update(machine.memory());
             if display.should_exit() {
                 break;
             }
-            
-            thread::sleep(Duration::from_millis(1));
+            if display.take_turbo_toggle() {
+                machine.set_turbo(!machine.is_turbo());
+            }
         }
-    });
-    // Start audio thread
-    let audio_memory = Arc::clone(&memory);
-    let audio_handle = thread::spawn(move || {
-        let mut audio = audio::Audio::new(Arc::clone(&audio_memory));
-        
-        loop {
-            audio.update();
-            thread::sleep(Duration::from_millis(1));
+        // audio.update(machine.memory());
+
+        if machine.is_halted() {
+            break; // Stop if CPU is halted
         }
-    });
-    */
-    // Run CPU at 1 MHz (each instruction takes varying cycles)
-    let cpu_memory = Arc::clone(&memory);
-    let cpu_handle = thread::spawn(move || {
-        let cycle_time = Duration::from_nanos(1_000); // 1 MHz = 1000ns per cycle
-        
-        loop {
-            let start = Instant::now();
-            
-            {
-                let mut cpu = cpu.lock().unwrap();
-                if !cpu.step() {
-                    break; // Stop if CPU is halted
-                }
-            }
-            
-            let elapsed = start.elapsed();
-            if elapsed < cycle_time {
-                thread::sleep(cycle_time - elapsed);
-            }
+
+        next_frame += FRAME_DURATION;
+        let now = Instant::now();
+        if next_frame > now {
+            thread::sleep(next_frame - now);
+        } else {
+            // Fell behind (e.g. the host was busy); don't try to catch up in a burst
+            next_frame = now;
         }
-    });
+    }
+}
+
+// Parses a clock rate like "1000000", "4MHz", "500kHz" or "unlimited" (None)
+fn parse_clock(value: &str) -> Option<Option<u64>> {
+    let value = value.trim().to_lowercase();
+    if value == "unlimited" {
+        return Some(None);
+    }
     
-    // Wait for threads to finish
-    cpu_handle.join().unwrap();
-    // display_handle.join().unwrap();
-    // audio_handle.join().unwrap();
+    let value = value.strip_suffix("hz").unwrap_or(&value);
+    let (digits, multiplier) = if let Some(digits) = value.strip_suffix('m') {
+        (digits, 1_000_000)
+    } else if let Some(digits) = value.strip_suffix('k') {
+        (digits, 1_000)
+    } else {
+        (value, 1)
+    };
+    
+    match digits.trim().parse::<u64>() {
+        Ok(hz) if hz > 0 => hz.checked_mul(multiplier).map(Some), // None if it overflows
+        _ => None,
+    }
 }
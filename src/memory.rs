@@ -1,4 +1,8 @@
 // src/memory.rs
+use crate::bus::{Bus, WriteFault};
+use crate::rng::{Rng, RNG_DATA, RNG_SEED_HI, RNG_SEED_LO};
+use crate::timer::{Timer, TIMER_COUNT, TIMER_SIZE, TIMER_START};
+
 pub const ROM_START: usize = 0x0000;
 pub const ROM_SIZE: usize = 0x8000;   // 32KB ROM
 pub const RAM_START: usize = 0x8000;
@@ -7,11 +11,44 @@ pub const DISPLAY_START: usize = 0xF000;
 pub const DISPLAY_SIZE: usize = 0x0C00; // 256x256 pixels, 1 byte per pixel (8 colors)
 pub const AUDIO_START: usize = 0xFC00;
 pub const AUDIO_SIZE: usize = 0x0100;  // 256 bytes audio buffer
+pub const IO_START: usize = 0xFD00;    // Memory-mapped devices
+pub const IO_SIZE: usize = 0x0100;
+pub const VECTORS_START: usize = 0xFFF8;
+pub const VECTORS_SIZE: usize = 0x0008; // Illegal opcode trap, NMI, RESET and IRQ vectors
 pub const MEMORY_SIZE: usize = 0x10000; // 64KB total address space
 
+// Banked ROM. $0000-$3FFF always holds bank 0 and $4000-$7FFF is a window onto
+// bank 1 or any bank appended after the 64KB address space image.
+pub const BANK_SIZE: usize = 0x4000;      // 16KB banks
+pub const BANK_WINDOW_START: usize = 0x4000;
+pub const BANK_SELECT: usize = IO_START + 0x20; // $FD20, write a bank number to switch the window
+pub const DEFAULT_BANK: u8 = 1;
+pub const EXTRA_BANKS_START: usize = MEMORY_SIZE; // Image offset of bank 2
+
+// Write protection covers the ROM above the zero page and stack, which live at
+// $0000-$01FF and have to stay writable
+pub const PROTECTED_START: usize = 0x0200;
+pub const PROTECTED_END: usize = ROM_START + ROM_SIZE;
+
+// What happens when a program writes to protected ROM
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum RomWriteProtection {
+    #[default]
+    Off,    // ROM is writable like RAM
+    Ignore, // Drop the write silently
+    Log,    // Drop the write and report it to the host
+    Break,  // Drop the write, report it and halt the CPU
+}
+
 pub struct Memory {
     data: [u8; MEMORY_SIZE],
     display_buffer: [u8; DISPLAY_SIZE], // Double buffer for display
+    rng: Rng,
+    timers: [Timer; TIMER_COUNT],
+    banks: Vec<[u8; BANK_SIZE]>, // Switchable banks, bank n at index n - 1
+    current_bank: u8,
+    rom_write_protection: RomWriteProtection,
+    write_fault: Option<WriteFault>,
 }
 
 impl Memory {
@@ -19,23 +56,117 @@ impl Memory {
         Self {
             data: [0; MEMORY_SIZE],
             display_buffer: [0; DISPLAY_SIZE],
+            rng: Rng::default(),
+            timers: Default::default(),
+            banks: vec![[0; BANK_SIZE]],
+            current_bank: DEFAULT_BANK,
+            rom_write_protection: RomWriteProtection::Off,
+            write_fault: None,
+        }
+    }
+    
+    // Put the memory-mapped devices back in their power-on state. Memory contents are kept.
+    pub fn reset(&mut self) {
+        self.rng.reset();
+        for timer in &mut self.timers {
+            timer.reset();
         }
+        self.switch_bank(DEFAULT_BANK);
+        self.write_fault = None;
+    }
+    
+    pub fn set_rom_write_protection(&mut self, protection: RomWriteProtection) {
+        self.rom_write_protection = protection;
     }
     
+    // Seed the random number source; the same seed always gives ROMs the same numbers
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng.set_seed(seed);
+    }
+    
+    // Reads without side effects, so device registers read back what was last written
     pub fn read(&self, address: u16) -> u8 {
+        if !is_io(address) {
+            return self.data[address as usize];
+        }
+        if address as usize == BANK_SELECT {
+            return self.current_bank;
+        }
+        if let Some((timer, register)) = timer_register(address) {
+            return self.timers[timer].read(register);
+        }
         self.data[address as usize]
     }
     
     pub fn write(&mut self, address: u16, value: u8) {
+        if (PROTECTED_START..PROTECTED_END).contains(&(address as usize)) {
+            match self.rom_write_protection {
+                RomWriteProtection::Off => {},
+                RomWriteProtection::Ignore => return,
+                RomWriteProtection::Log | RomWriteProtection::Break => {
+                    // Keep the first fault until the CPU collects it
+                    let halt = self.rom_write_protection == RomWriteProtection::Break;
+                    self.write_fault.get_or_insert(WriteFault { address, value, halt });
+                    return;
+                },
+            }
+        }
+        
         self.data[address as usize] = value;
         // When writing to display memory, update the double buffer
         if (address as usize) >= DISPLAY_START && (address as usize) < DISPLAY_START + DISPLAY_SIZE {
             let display_offset = (address as usize) - DISPLAY_START;
             self.display_buffer[display_offset] = value;
         }
+        
+        if !is_io(address) {
+            return;
+        }
+        if address as usize == RNG_SEED_LO || address as usize == RNG_SEED_HI {
+            let seed = ((self.data[RNG_SEED_HI] as u16) << 8) | self.data[RNG_SEED_LO] as u16;
+            self.rng.reseed(seed);
+        }
+        
+        if let Some((timer, register)) = timer_register(address) {
+            self.timers[timer].write(register, value);
+        }
+        
+        if address as usize == BANK_SELECT {
+            self.switch_bank(value);
+        }
+    }
+    
+    // Map `bank` into $4000-$7FFF. Bank 0 is fixed at $0000 and can't be mapped,
+    // and selecting a bank the ROM doesn't have is ignored.
+    pub fn switch_bank(&mut self, bank: u8) {
+        if bank == 0 || bank as usize > self.banks.len() {
+            return;
+        }
+        
+        // The window is writable, so save it back before swapping in the new bank
+        let window = BANK_WINDOW_START..BANK_WINDOW_START + BANK_SIZE;
+        self.banks[self.current_bank as usize - 1].copy_from_slice(&self.data[window.clone()]);
+        self.data[window].copy_from_slice(&self.banks[bank as usize - 1]);
+        self.current_bank = bank;
+    }
+    
+    pub fn current_bank(&self) -> u8 {
+        self.current_bank
+    }
+    
+    pub fn bank_count(&self) -> usize {
+        self.banks.len() + 1
     }
     
-    pub fn load_program(&mut self, program: &[u8]) {
+    pub fn load_program(&mut self, program: &[u8]) -> Result<(), String> {
+        // Past the first 32KB, a full address space image only carries the vectors.
+        // Anything else there (RAM, display, audio, I/O) has nowhere to go.
+        let unused = ROM_START + ROM_SIZE..VECTORS_START.min(program.len());
+        if let Some(offset) = unused.into_iter().find(|&i| program[i] != 0) {
+            return Err(format!("ROM image has data at ${:04X}, between the ROM and the interrupt vectors", offset));
+        }
+        
+        // Bank 0 and bank 1 are the first 32KB of the image
         for (i, &byte) in program.iter().enumerate() {
             if i < ROM_SIZE {
                 self.data[ROM_START + i] = byte;
@@ -43,6 +174,27 @@ impl Memory {
                 break;
             }
         }
+        
+        // Images covering the whole address space (e.g. assembled with `.org $FFFC`)
+        // also carry the interrupt vectors
+        if program.len() > VECTORS_START {
+            for (i, &byte) in program[VECTORS_START..].iter().take(VECTORS_SIZE).enumerate() {
+                self.data[VECTORS_START + i] = byte;
+            }
+        }
+        
+        // Banks 2 and up follow the 64KB image, 16KB each. A short last bank is padded with zeros.
+        self.banks = vec![[0; BANK_SIZE]];
+        self.banks[0].copy_from_slice(&self.data[BANK_WINDOW_START..BANK_WINDOW_START + BANK_SIZE]);
+        if program.len() > EXTRA_BANKS_START {
+            for chunk in program[EXTRA_BANKS_START..].chunks(BANK_SIZE) {
+                let mut bank = [0; BANK_SIZE];
+                bank[..chunk.len()].copy_from_slice(chunk);
+                self.banks.push(bank);
+            }
+        }
+        self.current_bank = DEFAULT_BANK;
+        Ok(())
     }
     
     pub fn get_display_buffer(&self) -> &[u8] {
@@ -59,3 +211,51 @@ impl Memory {
         }
     }
 }
+
+// Whether an address is in the memory-mapped device range
+fn is_io(address: u16) -> bool {
+    (IO_START..IO_START + IO_SIZE).contains(&(address as usize))
+}
+
+// Which timer and register an address belongs to, if it's in the timer block
+fn timer_register(address: u16) -> Option<(usize, usize)> {
+    let offset = (address as usize).checked_sub(TIMER_START)?;
+    if offset < TIMER_COUNT * TIMER_SIZE {
+        Some((offset / TIMER_SIZE, offset % TIMER_SIZE))
+    } else {
+        None
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for Memory {
+    fn read(&mut self, address: u16) -> u8 {
+        if address as usize == RNG_DATA {
+            return self.rng.next_byte();
+        }
+        Memory::read(self, address)
+    }
+    
+    fn write(&mut self, address: u16, value: u8) {
+        Memory::write(self, address, value);
+    }
+    
+    fn tick(&mut self, cycles: u32) {
+        for timer in &mut self.timers {
+            timer.tick(cycles);
+        }
+    }
+    
+    fn irq(&self) -> bool {
+        self.timers.iter().any(|timer| timer.irq())
+    }
+    
+    fn take_write_fault(&mut self) -> Option<WriteFault> {
+        self.write_fault.take()
+    }
+}
@@ -0,0 +1,64 @@
+// src/rng.rs
+// Hardware random number source. A xorshift32 generator exposed to ROMs through
+// memory-mapped registers, so the same seed always produces the same sequence.
+
+use crate::memory::IO_START;
+
+pub const RNG_DATA: usize = IO_START;        // $FD00, read: next random byte
+pub const RNG_SEED_LO: usize = IO_START + 1; // $FD01, write: reseed from the 16 bit seed registers
+pub const RNG_SEED_HI: usize = IO_START + 2; // $FD02
+
+const DEFAULT_SEED: u64 = 0x4845_4C49_4F53; // "HELIOS"
+
+pub struct Rng {
+    seed: u64,
+    state: u32,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Self { seed, state: 0 };
+        rng.reset();
+        rng
+    }
+    
+    // Restart the sequence from the seed the host gave us
+    pub fn reset(&mut self) {
+        self.state = Self::initial_state(self.seed);
+    }
+    
+    // Set a new host seed and restart the sequence from it
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.reset();
+    }
+    
+    // Restart from a seed written by the ROM, without replacing the host seed
+    pub fn reseed(&mut self, seed: u16) {
+        self.state = Self::initial_state(seed as u64);
+    }
+    
+    pub fn next_byte(&mut self) -> u8 {
+        // xorshift32
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        (x >> 24) as u8
+    }
+    
+    fn initial_state(seed: u64) -> u32 {
+        // Fold the seed into 32 bits and mix it so small seeds don't start out
+        // with long runs of zero bits. xorshift gets stuck on an all zero state.
+        let folded = (seed ^ (seed >> 32)) as u32;
+        let state = folded.wrapping_mul(0x9E37_79B9) ^ 0xA5A5_A5A5;
+        if state == 0 { 1 } else { state }
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new(DEFAULT_SEED)
+    }
+}
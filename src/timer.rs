@@ -0,0 +1,211 @@
+// src/timer.rs
+// Programmable countdown timers clocked by CPU cycles. Each timer counts down
+// once every (prescaler + 1) cycles; when it reaches zero it sets its expired
+// bit, reloads and keeps going, and can raise an IRQ until the ROM acknowledges it.
+
+use crate::memory::IO_START;
+
+pub const TIMER_START: usize = IO_START + 0x10; // $FD10
+pub const TIMER_COUNT: usize = 2;
+pub const TIMER_SIZE: usize = 0x08; // Registers per timer
+
+// Register offsets within a timer
+pub const TIMER_RELOAD_LO: usize = 0x00;  // Reload value, 0 means 65536
+pub const TIMER_RELOAD_HI: usize = 0x01;
+pub const TIMER_PRESCALER: usize = 0x02;  // Count every (prescaler + 1) cycles
+pub const TIMER_CONTROL: usize = 0x03;
+pub const TIMER_COUNTER_LO: usize = 0x04; // Current count (read only)
+pub const TIMER_COUNTER_HI: usize = 0x05;
+
+// Control register bits
+pub const TIMER_ENABLE: u8 = 0b00000001;     // Starting the timer loads the counter from the reload value
+pub const TIMER_IRQ_ENABLE: u8 = 0b00000010; // Raise an IRQ while the expired bit is set
+pub const TIMER_EXPIRED: u8 = 0b10000000;    // Set on reaching zero, write 1 to clear
+
+#[derive(Default)]
+pub struct Timer {
+    reload: u16,
+    prescaler: u8,
+    enabled: bool,
+    irq_enabled: bool,
+    expired: bool,
+    counter: u32,        // 1..=65536 while running
+    prescale_count: u32, // Cycles since the counter last moved
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+    
+    pub fn read(&self, register: usize) -> u8 {
+        match register {
+            TIMER_RELOAD_LO => self.reload as u8,
+            TIMER_RELOAD_HI => (self.reload >> 8) as u8,
+            TIMER_PRESCALER => self.prescaler,
+            TIMER_CONTROL => {
+                let mut control = 0;
+                if self.enabled { control |= TIMER_ENABLE; }
+                if self.irq_enabled { control |= TIMER_IRQ_ENABLE; }
+                if self.expired { control |= TIMER_EXPIRED; }
+                control
+            },
+            TIMER_COUNTER_LO => self.counter as u8,
+            TIMER_COUNTER_HI => (self.counter >> 8) as u8,
+            _ => 0,
+        }
+    }
+    
+    pub fn write(&mut self, register: usize, value: u8) {
+        match register {
+            TIMER_RELOAD_LO => self.reload = (self.reload & 0xFF00) | value as u16,
+            TIMER_RELOAD_HI => self.reload = (self.reload & 0x00FF) | ((value as u16) << 8),
+            TIMER_PRESCALER => self.prescaler = value,
+            TIMER_CONTROL => {
+                let enable = value & TIMER_ENABLE != 0;
+                if enable && !self.enabled {
+                    self.counter = self.period();
+                    self.prescale_count = 0;
+                }
+                self.enabled = enable;
+                self.irq_enabled = value & TIMER_IRQ_ENABLE != 0;
+                if value & TIMER_EXPIRED != 0 {
+                    self.expired = false;
+                }
+            },
+            _ => {}
+        }
+    }
+    
+    // Advance the timer by `cycles` CPU cycles
+    pub fn tick(&mut self, cycles: u32) {
+        if !self.enabled {
+            return;
+        }
+        
+        let divider = self.prescaler as u32 + 1;
+        self.prescale_count += cycles;
+        let steps = self.prescale_count / divider;
+        self.prescale_count %= divider;
+        
+        if steps >= self.counter {
+            // Expired at least once; carry the leftover steps into the reloaded count
+            let leftover = (steps - self.counter) % self.period();
+            self.counter = self.period() - leftover;
+            self.expired = true;
+        } else {
+            self.counter -= steps;
+        }
+    }
+    
+    pub fn irq(&self) -> bool {
+        self.irq_enabled && self.expired
+    }
+    
+    fn period(&self) -> u32 {
+        if self.reload == 0 { 0x10000 } else { self.reload as u32 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::memory::Memory;
+    
+    // A running timer with the given reload value and prescaler
+    fn timer(reload: u16, prescaler: u8, control: u8) -> Timer {
+        let mut timer = Timer::new();
+        timer.write(TIMER_RELOAD_LO, reload as u8);
+        timer.write(TIMER_RELOAD_HI, (reload >> 8) as u8);
+        timer.write(TIMER_PRESCALER, prescaler);
+        timer.write(TIMER_CONTROL, control);
+        timer
+    }
+    
+    fn counter(timer: &Timer) -> u16 {
+        u16::from_le_bytes([timer.read(TIMER_COUNTER_LO), timer.read(TIMER_COUNTER_HI)])
+    }
+    
+    #[test]
+    fn counts_down_and_reloads() {
+        let mut timer = timer(3, 1, TIMER_ENABLE);
+        assert_eq!(counter(&timer), 3);
+        
+        // One step every 2 cycles
+        timer.tick(3);
+        assert_eq!(counter(&timer), 2);
+        assert_eq!(timer.read(TIMER_CONTROL) & TIMER_EXPIRED, 0);
+        
+        // Reaching zero reloads, and the leftover steps carry into the new count
+        timer.tick(7);
+        assert_eq!(counter(&timer), 1);
+        assert_eq!(timer.read(TIMER_CONTROL), TIMER_ENABLE | TIMER_EXPIRED);
+    }
+    
+    #[test]
+    fn reload_zero_is_65536() {
+        let mut timer = timer(0, 0, TIMER_ENABLE);
+        timer.tick(0xFFFF);
+        assert_eq!(counter(&timer), 1);
+        assert_eq!(timer.read(TIMER_CONTROL), TIMER_ENABLE);
+        timer.tick(1);
+        assert_eq!(timer.read(TIMER_CONTROL), TIMER_ENABLE | TIMER_EXPIRED);
+        assert_eq!(counter(&timer), 0);
+    }
+    
+    #[test]
+    fn irq_until_acknowledged() {
+        let mut timer = timer(2, 0, TIMER_ENABLE | TIMER_IRQ_ENABLE);
+        timer.tick(1);
+        assert!(!timer.irq());
+        timer.tick(1);
+        assert!(timer.irq());
+        
+        // Writing the control register without the expired bit leaves it set
+        timer.write(TIMER_CONTROL, TIMER_ENABLE | TIMER_IRQ_ENABLE);
+        assert!(timer.irq());
+        
+        // Writing 1 to the expired bit acknowledges it and keeps the timer running
+        timer.write(TIMER_CONTROL, TIMER_ENABLE | TIMER_IRQ_ENABLE | TIMER_EXPIRED);
+        assert!(!timer.irq());
+        assert_eq!(timer.read(TIMER_CONTROL), TIMER_ENABLE | TIMER_IRQ_ENABLE);
+        timer.tick(2);
+        assert!(timer.irq());
+    }
+    
+    #[test]
+    fn no_irq_when_disabled() {
+        let mut timer = timer(1, 0, TIMER_ENABLE);
+        timer.tick(1);
+        assert_eq!(timer.read(TIMER_CONTROL), TIMER_ENABLE | TIMER_EXPIRED);
+        assert!(!timer.irq());
+    }
+    
+    #[test]
+    fn second_timer() {
+        // The second timer's registers start at $FD18
+        let base = (TIMER_START + TIMER_SIZE) as u16;
+        assert_eq!(base, 0xFD18);
+        
+        let mut memory = Memory::new();
+        memory.write(base + TIMER_RELOAD_LO as u16, 4);
+        memory.write(base + TIMER_CONTROL as u16, TIMER_ENABLE | TIMER_IRQ_ENABLE);
+        assert_eq!(memory.read(base + TIMER_COUNTER_LO as u16), 4);
+        // The first timer isn't touched
+        assert_eq!(memory.read(TIMER_START as u16 + TIMER_CONTROL as u16), 0);
+        
+        Bus::tick(&mut memory, 3);
+        assert_eq!(memory.read(base + TIMER_COUNTER_LO as u16), 1);
+        assert!(!Bus::irq(&memory));
+        Bus::tick(&mut memory, 1);
+        assert!(Bus::irq(&memory));
+        
+        memory.write(base + TIMER_CONTROL as u16, TIMER_ENABLE | TIMER_IRQ_ENABLE | TIMER_EXPIRED);
+        assert!(!Bus::irq(&memory));
+    }
+}
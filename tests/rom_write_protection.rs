@@ -0,0 +1,73 @@
+// tests/rom_write_protection.rs
+// Runs a ROM that stores into protected ROM under each RomWriteProtection mode
+// and checks what happens to the byte, the events and the CPU.
+use helios::compiler;
+use helios::cpu::{CpuError, CpuEvent};
+use helios::memory::RomWriteProtection;
+use helios::Machine;
+
+// The STA is at $0002, just after the 2 byte LDA
+const SOURCE: &str = "
+    LDA #$42
+    STA $0300
+    LDA #$01
+    HLT
+";
+const STORE_PC: u16 = 0x0002;
+const TARGET: u16 = 0x0300;
+
+const MAX_STEPS: usize = 100;
+
+// Runs the ROM until it halts, returning the machine and the events it raised
+fn run(protection: RomWriteProtection) -> (Machine, Vec<CpuEvent>) {
+    let binary = compiler::compile(SOURCE).unwrap();
+    let mut machine = Machine::new();
+    machine.load_rom(&binary).unwrap();
+    machine.set_rom_write_protection(protection);
+
+    let mut events = Vec::new();
+    for _ in 0..MAX_STEPS {
+        if machine.is_halted() {
+            return (machine, events);
+        }
+        events.extend(machine.step().event);
+    }
+    panic!("{:?} didn't halt", protection);
+}
+
+#[test]
+fn off_writes_rom() {
+    let (machine, events) = run(RomWriteProtection::Off);
+    assert_eq!(machine.memory().read(TARGET), 0x42);
+    assert_eq!(events, [CpuEvent::Halted]);
+}
+
+#[test]
+fn ignore_drops_write() {
+    let (machine, events) = run(RomWriteProtection::Ignore);
+    assert_eq!(machine.memory().read(TARGET), 0x00);
+    assert_eq!(events, [CpuEvent::Halted]);
+    assert_eq!(machine.last_error(), None);
+}
+
+#[test]
+fn log_reports_write() {
+    let (machine, events) = run(RomWriteProtection::Log);
+    assert_eq!(machine.memory().read(TARGET), 0x00);
+    assert_eq!(events, [
+        CpuEvent::RomWrite { pc: STORE_PC, address: TARGET, value: 0x42 },
+        CpuEvent::Halted,
+    ]);
+    // The program carries on after the store
+    assert_eq!(machine.cpu().a, 0x01);
+}
+
+#[test]
+fn break_halts_on_write() {
+    let (machine, events) = run(RomWriteProtection::Break);
+    assert_eq!(machine.memory().read(TARGET), 0x00);
+    assert_eq!(events, [CpuEvent::RomWrite { pc: STORE_PC, address: TARGET, value: 0x42 }]);
+    assert_eq!(machine.last_error(), Some(CpuError::RomWrite { pc: STORE_PC, address: TARGET, value: 0x42 }));
+    // Halted at the store, before the second LDA
+    assert_eq!(machine.cpu().a, 0x42);
+}
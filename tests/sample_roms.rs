@@ -0,0 +1,82 @@
+// tests/sample_roms.rs
+// Assembles the sample ROMs in roms/ and checks the values their DBG
+// instructions print. audio-test and debug-test loop forever, and simple-test
+// doesn't print anything, so they're left out.
+use std::path::Path;
+
+use helios::compiler;
+use helios::cpu::CpuEvent;
+use helios::Machine;
+
+// Far more than any of the samples needs to reach its HLT
+const MAX_CYCLES: u64 = 1_000_000;
+
+// Runs roms/`name` until it halts, returning the values printed by DBG
+fn run_sample(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("roms").join(name);
+    let binary = compiler::compile_file(&path).unwrap_or_else(|err| panic!("{}: {}", name, err));
+
+    let mut machine = Machine::new();
+    machine.load_rom(&binary).unwrap();
+
+    let mut values = Vec::new();
+    while !machine.is_halted() {
+        assert!(machine.cycles() < MAX_CYCLES, "{} didn't halt", name);
+        if let Some(CpuEvent::Debug { value, .. }) = machine.step().event {
+            values.push(value);
+        }
+    }
+    assert_eq!(machine.last_error(), None, "{}", name);
+    values
+}
+
+#[test]
+fn bank_test() {
+    assert_eq!(run_sample("bank-test.asm"), [2, 3, 3, 3]);
+}
+
+#[test]
+fn bcd_test() {
+    assert_eq!(run_sample("bcd-test.asm"), [4, 1, 9, 0x99]);
+}
+
+#[test]
+fn block_test() {
+    assert_eq!(run_sample("block-test.asm"), [1, 4, 1, 4]);
+}
+
+#[test]
+fn expr_test() {
+    assert_eq!(run_sample("expr-test.asm"), [90, 13, 31, 30, 40, 4, 65, 59, 32, 34, 2]);
+}
+
+#[test]
+fn include_test() {
+    assert_eq!(run_sample("include-test.asm"), [5, 40, 4]);
+}
+
+#[test]
+fn label_test() {
+    assert_eq!(run_sample("label-test.asm"), [1, 36]);
+}
+
+#[test]
+fn macro_test() {
+    assert_eq!(run_sample("macro-test.asm"), [42, 15, 38, 0, 7, 15]);
+}
+
+#[test]
+fn muldiv_test() {
+    assert_eq!(run_sample("muldiv-test.asm"), [88, 2, 200, 14, 2, 14]);
+}
+
+#[test]
+fn rng_test() {
+    // The ROM seeds the RNG itself, so the two numbers are the same on every run
+    assert_eq!(run_sample("rng-test.asm"), [153, 90, 1]);
+}
+
+#[test]
+fn timer_test() {
+    assert_eq!(run_sample("timer-test.asm"), [5]);
+}